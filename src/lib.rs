@@ -1,30 +1,664 @@
 #![no_std]
 use soroban_sdk::{
-    assert_with_error, contract, contracterror, contractimpl, contracttype, symbol_short, vec,
-    BytesN, Env, Symbol, Vec,
+    assert_with_error, contracterror, contracttype, panic_with_error, vec, Bytes, BytesN, Env, Vec,
+};
+#[cfg(feature = "helper-contract")]
+use soroban_sdk::{
+    contract, contractimpl, symbol_short, xdr::ToXdr, Address, IntoVal, Symbol, TryFromVal, Val,
 };
 use tiny_keccak::{Hasher, Keccak};
 
+mod mmr;
+pub use mmr::{Mmr, MmrProof};
+
+#[cfg(test)]
+extern crate std;
+
+#[cfg(test)]
+std::thread_local! {
+    /// Calls to `MerkleTree::keccak256` made by the current test thread.
+    static KECCAK_CALLS: core::cell::Cell<u64> = const { core::cell::Cell::new(0) };
+}
+
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 #[repr(u32)]
 pub enum Error {
     MerkleTreeFull = 1,
     MerkleTreeInvalidVecSize = 2,
+    AlreadyInitialized = 3,
+    SaltNotSet = 4,
+    StateCorrupted = 5,
+    LeavesNotStored = 6,
+    InvalidRange = 7,
+    Unauthorized = 8,
+    AdminNotSet = 9,
+    UnsupportedHashAlgo = 10,
+    InvalidZeroHashes = 11,
+    TreeNotMutable = 12,
+    InvalidProof = 13,
+    NullifierUsed = 14,
+    InvalidFanout = 15,
+    FrontierInconsistent = 16,
+    TagAlreadyCommitted = 17,
+    InvalidDepth = 18,
+    MerkleTreeIndexOutOfRange = 19,
+    InvalidNullifier = 20,
+    RootNotFrozen = 21,
+}
+
+/// Depth of `MerkleTree::new`, and of the contract tree unless
+/// `TreeConfig::depth` says otherwise, see `Contract::get_depth`.
+pub const TREE_DEPTH: usize = 32;
+/// Leaves a tree of `TREE_DEPTH` holds.
+pub const MAX_LEAVES: u64 = u64::pow(2, TREE_DEPTH as u32);
+/// Deepest tree the zero hash tables cover, the depth of `MerkleTree::new`.
+pub const MAX_SUPPORTED_DEPTH: u32 = TREE_DEPTH as u32;
+
+/// Zero hashes of every level, the all zero leaf first and each entry the
+/// keccak256 of the previous one with itself.
+const ZERO_HASHES: [[u8; 32]; TREE_DEPTH] = [
+    [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0,
+    ],
+    [
+        173, 50, 40, 182, 118, 247, 211, 205, 66, 132, 165, 68, 63, 23, 241, 150, 43, 54, 228, 145,
+        179, 10, 64, 178, 64, 88, 73, 229, 151, 186, 95, 181,
+    ],
+    [
+        180, 193, 25, 81, 149, 124, 111, 143, 100, 44, 74, 246, 28, 214, 178, 70, 64, 254, 198,
+        220, 127, 198, 7, 238, 130, 6, 169, 158, 146, 65, 13, 48,
+    ],
+    [
+        33, 221, 185, 163, 86, 129, 92, 63, 172, 16, 38, 182, 222, 197, 223, 49, 36, 175, 186, 219,
+        72, 92, 155, 165, 163, 227, 57, 138, 4, 183, 186, 133,
+    ],
+    [
+        229, 135, 105, 179, 42, 27, 234, 241, 234, 39, 55, 90, 68, 9, 90, 13, 31, 182, 100, 206,
+        45, 211, 88, 231, 252, 191, 183, 140, 38, 161, 147, 68,
+    ],
+    [
+        14, 176, 30, 191, 201, 237, 39, 80, 12, 212, 223, 201, 121, 39, 45, 31, 9, 19, 204, 159,
+        102, 84, 13, 126, 128, 5, 129, 17, 9, 225, 207, 45,
+    ],
+    [
+        136, 124, 34, 189, 135, 80, 211, 64, 22, 172, 60, 102, 181, 255, 16, 45, 172, 221, 115,
+        246, 176, 20, 231, 16, 181, 30, 128, 34, 175, 154, 25, 104,
+    ],
+    [
+        255, 215, 1, 87, 228, 128, 99, 252, 51, 201, 122, 5, 15, 127, 100, 2, 51, 191, 100, 108,
+        201, 141, 149, 36, 198, 185, 43, 207, 58, 181, 111, 131,
+    ],
+    [
+        152, 103, 204, 95, 127, 25, 107, 147, 186, 225, 226, 126, 99, 32, 116, 36, 69, 210, 144,
+        242, 38, 56, 39, 73, 139, 84, 254, 197, 57, 247, 86, 175,
+    ],
+    [
+        206, 250, 212, 229, 8, 192, 152, 185, 167, 225, 216, 254, 177, 153, 85, 251, 2, 186, 150,
+        117, 88, 80, 120, 113, 9, 105, 211, 68, 15, 80, 84, 224,
+    ],
+    [
+        249, 220, 62, 127, 224, 22, 224, 80, 239, 242, 96, 51, 79, 24, 165, 212, 254, 57, 29, 130,
+        9, 35, 25, 245, 150, 79, 46, 46, 183, 193, 195, 165,
+    ],
+    [
+        248, 177, 58, 73, 226, 130, 246, 9, 195, 23, 168, 51, 251, 141, 151, 109, 17, 81, 124, 87,
+        29, 18, 33, 162, 101, 210, 90, 247, 120, 236, 248, 146,
+    ],
+    [
+        52, 144, 198, 206, 235, 69, 10, 236, 220, 130, 226, 130, 147, 3, 29, 16, 199, 215, 59, 248,
+        94, 87, 191, 4, 26, 151, 54, 10, 162, 197, 217, 156,
+    ],
+    [
+        193, 223, 130, 217, 196, 184, 116, 19, 234, 226, 239, 4, 143, 148, 180, 211, 85, 76, 234,
+        115, 217, 43, 15, 122, 249, 110, 2, 113, 198, 145, 226, 187,
+    ],
+    [
+        92, 103, 173, 215, 198, 202, 243, 2, 37, 106, 222, 223, 122, 177, 20, 218, 10, 207, 232,
+        112, 212, 73, 163, 164, 137, 247, 129, 214, 89, 232, 190, 204,
+    ],
+    [
+        218, 123, 206, 159, 78, 134, 24, 182, 189, 47, 65, 50, 206, 121, 140, 220, 122, 96, 231,
+        225, 70, 10, 114, 153, 227, 198, 52, 42, 87, 150, 38, 210,
+    ],
+    [
+        39, 51, 229, 15, 82, 110, 194, 250, 25, 162, 43, 49, 232, 237, 80, 242, 60, 209, 253, 249,
+        76, 145, 84, 237, 58, 118, 9, 162, 241, 255, 152, 31,
+    ],
+    [
+        225, 211, 181, 200, 7, 178, 129, 228, 104, 60, 198, 214, 49, 92, 249, 91, 154, 222, 134,
+        65, 222, 252, 179, 35, 114, 241, 193, 38, 227, 152, 239, 122,
+    ],
+    [
+        90, 45, 206, 10, 138, 127, 104, 187, 116, 86, 15, 143, 113, 131, 124, 44, 46, 187, 203,
+        247, 255, 251, 66, 174, 24, 150, 241, 63, 124, 116, 121, 160,
+    ],
+    [
+        180, 106, 40, 182, 245, 85, 64, 248, 148, 68, 246, 61, 224, 55, 142, 61, 18, 27, 224, 158,
+        6, 204, 157, 237, 28, 32, 230, 88, 118, 211, 106, 160,
+    ],
+    [
+        198, 94, 150, 69, 100, 71, 134, 182, 32, 226, 221, 42, 214, 72, 221, 252, 191, 74, 126, 91,
+        26, 58, 78, 207, 231, 246, 70, 103, 163, 240, 183, 226,
+    ],
+    [
+        244, 65, 133, 136, 237, 53, 162, 69, 140, 255, 235, 57, 185, 61, 38, 241, 141, 42, 177, 59,
+        220, 230, 174, 229, 142, 123, 153, 53, 158, 194, 223, 217,
+    ],
+    [
+        90, 156, 22, 220, 0, 214, 239, 24, 183, 147, 58, 111, 141, 198, 92, 203, 85, 102, 113, 56,
+        119, 111, 125, 234, 16, 16, 112, 220, 135, 150, 227, 119,
+    ],
+    [
+        77, 248, 79, 64, 174, 12, 130, 41, 208, 214, 6, 158, 92, 143, 57, 167, 194, 153, 103, 122,
+        9, 211, 103, 252, 123, 5, 227, 188, 56, 14, 230, 82,
+    ],
+    [
+        205, 199, 37, 149, 247, 76, 123, 16, 67, 208, 225, 255, 186, 183, 52, 100, 140, 131, 141,
+        251, 5, 39, 217, 113, 182, 2, 188, 33, 108, 150, 25, 239,
+    ],
+    [
+        10, 191, 90, 201, 116, 161, 237, 87, 244, 5, 10, 165, 16, 221, 156, 116, 245, 8, 39, 123,
+        57, 215, 151, 59, 178, 223, 204, 197, 238, 176, 97, 141,
+    ],
+    [
+        184, 205, 116, 4, 111, 243, 55, 240, 167, 191, 44, 142, 3, 225, 15, 100, 44, 24, 134, 121,
+        141, 113, 128, 106, 177, 232, 136, 217, 229, 238, 135, 208,
+    ],
+    [
+        131, 140, 86, 85, 203, 33, 198, 203, 131, 49, 59, 90, 99, 17, 117, 223, 244, 150, 55, 114,
+        204, 233, 16, 129, 136, 179, 74, 200, 124, 129, 196, 30,
+    ],
+    [
+        102, 46, 228, 221, 45, 215, 178, 188, 112, 121, 97, 177, 230, 70, 196, 4, 118, 105, 220,
+        182, 88, 79, 13, 141, 119, 13, 175, 93, 126, 125, 235, 46,
+    ],
+    [
+        56, 138, 178, 14, 37, 115, 209, 113, 168, 129, 8, 231, 157, 130, 14, 152, 242, 108, 11,
+        132, 170, 139, 47, 74, 164, 150, 141, 187, 129, 142, 163, 34,
+    ],
+    [
+        147, 35, 124, 80, 186, 117, 238, 72, 95, 76, 34, 173, 242, 247, 65, 64, 11, 223, 141, 106,
+        156, 199, 223, 126, 202, 229, 118, 34, 22, 101, 215, 53,
+    ],
+    [
+        132, 72, 129, 139, 180, 174, 69, 98, 132, 158, 148, 158, 23, 172, 22, 224, 190, 22, 104,
+        142, 21, 107, 92, 241, 94, 9, 140, 98, 124, 0, 86, 169,
+    ],
+];
+
+/// Same as `ZERO_HASHES` with SHA-256.
+const SHA256_ZERO_HASHES: [[u8; 32]; TREE_DEPTH] = [
+    [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0,
+    ],
+    [
+        245, 165, 253, 66, 209, 106, 32, 48, 39, 152, 239, 110, 211, 9, 151, 155, 67, 0, 61, 35,
+        32, 217, 240, 232, 234, 152, 49, 169, 39, 89, 251, 75,
+    ],
+    [
+        219, 86, 17, 78, 0, 253, 212, 193, 248, 92, 137, 43, 243, 90, 201, 168, 146, 137, 170, 236,
+        177, 235, 208, 169, 108, 222, 96, 106, 116, 139, 93, 113,
+    ],
+    [
+        199, 128, 9, 253, 240, 127, 197, 106, 17, 241, 34, 55, 6, 88, 163, 83, 170, 165, 66, 237,
+        99, 228, 76, 75, 193, 95, 244, 205, 16, 90, 179, 60,
+    ],
+    [
+        83, 109, 152, 131, 127, 45, 209, 101, 165, 93, 94, 234, 233, 20, 133, 149, 68, 114, 213,
+        111, 36, 109, 242, 86, 191, 60, 174, 25, 53, 42, 18, 60,
+    ],
+    [
+        158, 253, 224, 82, 170, 21, 66, 159, 174, 5, 186, 212, 208, 177, 215, 198, 77, 166, 77, 3,
+        215, 161, 133, 74, 88, 140, 44, 184, 67, 12, 13, 48,
+    ],
+    [
+        216, 141, 223, 238, 212, 0, 168, 117, 85, 150, 178, 25, 66, 193, 73, 126, 17, 76, 48, 46,
+        97, 24, 41, 15, 145, 230, 119, 41, 118, 4, 31, 161,
+    ],
+    [
+        135, 235, 13, 219, 165, 126, 53, 246, 210, 134, 103, 56, 2, 164, 175, 89, 117, 226, 37, 6,
+        199, 207, 76, 100, 187, 107, 229, 238, 17, 82, 127, 44,
+    ],
+    [
+        38, 132, 100, 118, 253, 95, 197, 74, 93, 67, 56, 81, 103, 201, 81, 68, 242, 100, 63, 83,
+        60, 200, 91, 185, 209, 107, 120, 47, 141, 125, 177, 147,
+    ],
+    [
+        80, 109, 134, 88, 45, 37, 36, 5, 184, 64, 1, 135, 146, 202, 210, 191, 18, 89, 241, 239, 90,
+        165, 248, 135, 225, 60, 178, 240, 9, 79, 81, 225,
+    ],
+    [
+        255, 255, 10, 215, 230, 89, 119, 47, 149, 52, 193, 149, 200, 21, 239, 196, 1, 78, 241, 225,
+        218, 237, 68, 4, 192, 99, 133, 209, 17, 146, 233, 43,
+    ],
+    [
+        108, 240, 65, 39, 219, 5, 68, 28, 216, 51, 16, 122, 82, 190, 133, 40, 104, 137, 14, 67, 23,
+        230, 160, 42, 180, 118, 131, 170, 117, 150, 66, 32,
+    ],
+    [
+        183, 208, 95, 135, 95, 20, 0, 39, 239, 81, 24, 162, 36, 123, 187, 132, 206, 143, 47, 15,
+        17, 35, 98, 48, 133, 218, 247, 150, 12, 50, 159, 95,
+    ],
+    [
+        223, 106, 245, 245, 187, 219, 107, 233, 239, 138, 166, 24, 228, 191, 128, 115, 150, 8, 103,
+        23, 30, 41, 103, 111, 139, 40, 77, 234, 106, 8, 168, 94,
+    ],
+    [
+        181, 141, 144, 15, 94, 24, 46, 60, 80, 239, 116, 150, 158, 161, 108, 119, 38, 197, 73, 117,
+        124, 194, 53, 35, 195, 105, 88, 125, 167, 41, 55, 132,
+    ],
+    [
+        212, 154, 117, 2, 255, 207, 176, 52, 11, 29, 120, 133, 104, 133, 0, 202, 48, 129, 97, 167,
+        249, 107, 98, 223, 157, 8, 59, 113, 252, 200, 242, 187,
+    ],
+    [
+        143, 230, 177, 104, 146, 86, 192, 211, 133, 244, 47, 91, 190, 32, 39, 162, 44, 25, 150,
+        225, 16, 186, 151, 193, 113, 211, 229, 148, 141, 233, 43, 235,
+    ],
+    [
+        141, 13, 99, 195, 158, 186, 222, 133, 9, 224, 174, 60, 156, 56, 118, 251, 95, 161, 18, 190,
+        24, 249, 5, 236, 172, 254, 203, 146, 5, 118, 3, 171,
+    ],
+    [
+        149, 238, 200, 178, 229, 65, 202, 212, 233, 29, 227, 131, 133, 242, 224, 70, 97, 159, 84,
+        73, 108, 35, 130, 203, 108, 172, 213, 185, 140, 38, 245, 164,
+    ],
+    [
+        248, 147, 233, 8, 145, 119, 117, 182, 43, 255, 35, 41, 77, 187, 227, 161, 205, 142, 108,
+        193, 195, 91, 72, 1, 136, 123, 100, 106, 111, 129, 241, 127,
+    ],
+    [
+        205, 219, 167, 181, 146, 227, 19, 51, 147, 193, 97, 148, 250, 199, 67, 26, 191, 47, 84,
+        133, 237, 113, 29, 178, 130, 24, 60, 129, 158, 8, 235, 170,
+    ],
+    [
+        138, 141, 127, 227, 175, 140, 170, 8, 90, 118, 57, 168, 50, 0, 20, 87, 223, 185, 18, 138,
+        128, 97, 20, 42, 208, 51, 86, 41, 255, 35, 255, 156,
+    ],
+    [
+        254, 179, 195, 55, 215, 165, 26, 111, 191, 0, 185, 227, 76, 82, 225, 201, 25, 92, 150, 155,
+        212, 231, 160, 191, 213, 29, 92, 91, 237, 156, 17, 103,
+    ],
+    [
+        231, 31, 10, 168, 60, 195, 46, 223, 190, 250, 159, 77, 62, 1, 116, 202, 133, 24, 46, 236,
+        159, 58, 9, 246, 166, 192, 223, 99, 119, 165, 16, 215,
+    ],
+    [
+        49, 32, 111, 168, 10, 80, 187, 106, 190, 41, 8, 80, 88, 241, 98, 18, 33, 42, 96, 238, 200,
+        240, 73, 254, 203, 146, 216, 200, 224, 168, 75, 192,
+    ],
+    [
+        33, 53, 43, 254, 203, 237, 221, 233, 147, 131, 159, 97, 76, 61, 172, 10, 62, 227, 117, 67,
+        249, 180, 18, 177, 97, 153, 220, 21, 142, 35, 181, 68,
+    ],
+    [
+        97, 158, 49, 39, 36, 187, 109, 124, 49, 83, 237, 157, 231, 145, 215, 100, 163, 102, 179,
+        137, 175, 19, 197, 139, 248, 168, 217, 4, 129, 164, 103, 101,
+    ],
+    [
+        124, 221, 41, 134, 38, 130, 80, 98, 141, 12, 16, 227, 133, 197, 140, 97, 145, 230, 251,
+        224, 81, 145, 188, 192, 79, 19, 63, 44, 234, 114, 193, 196,
+    ],
+    [
+        132, 137, 48, 189, 123, 168, 202, 197, 70, 97, 7, 33, 19, 251, 39, 136, 105, 224, 123, 184,
+        88, 127, 145, 57, 41, 51, 55, 77, 1, 123, 203, 225,
+    ],
+    [
+        136, 105, 255, 44, 34, 178, 140, 193, 5, 16, 217, 133, 50, 146, 128, 51, 40, 190, 79, 176,
+        232, 4, 149, 232, 187, 141, 39, 31, 91, 136, 150, 54,
+    ],
+    [
+        181, 254, 40, 231, 159, 27, 133, 15, 134, 88, 36, 108, 233, 182, 161, 231, 180, 159, 192,
+        109, 183, 20, 62, 143, 224, 180, 242, 176, 197, 82, 58, 92,
+    ],
+    [
+        152, 94, 146, 159, 112, 175, 40, 208, 189, 209, 169, 10, 128, 143, 151, 127, 89, 124, 124,
+        119, 140, 72, 158, 152, 211, 189, 137, 16, 211, 26, 192, 247,
+    ],
+];
+
+/// Same as `ZERO_HASHES` for `HashAlgo::Keccak256Prefixed`, where every
+/// internal node is `keccak256(0x01 || left || right)`.
+const PREFIXED_ZERO_HASHES: [[u8; 32]; TREE_DEPTH] = [
+    [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0,
+    ],
+    [
+        192, 122, 30, 139, 126, 0, 87, 103, 63, 220, 42, 255, 225, 144, 216, 169, 96, 197, 254, 97,
+        86, 99, 242, 123, 124, 232, 79, 61, 147, 239, 146, 166,
+    ],
+    [
+        253, 71, 81, 116, 116, 165, 151, 99, 125, 84, 3, 138, 6, 99, 209, 208, 59, 147, 27, 35,
+        141, 224, 107, 115, 227, 193, 44, 244, 67, 222, 110, 141,
+    ],
+    [
+        71, 168, 245, 232, 250, 112, 190, 39, 96, 55, 128, 103, 201, 198, 212, 16, 221, 150, 190,
+        7, 130, 11, 66, 48, 193, 18, 84, 199, 255, 16, 194, 152,
+    ],
+    [
+        174, 209, 156, 164, 191, 226, 54, 91, 27, 51, 250, 148, 116, 76, 208, 198, 162, 213, 80,
+        80, 108, 126, 126, 252, 7, 56, 121, 203, 121, 69, 155, 154,
+    ],
+    [
+        110, 105, 152, 167, 218, 139, 45, 181, 201, 142, 184, 83, 9, 157, 140, 174, 198, 55, 151,
+        181, 40, 59, 125, 172, 55, 178, 255, 182, 48, 168, 110, 36,
+    ],
+    [
+        24, 28, 25, 115, 91, 255, 35, 181, 91, 194, 149, 252, 11, 96, 193, 197, 199, 40, 130, 9,
+        178, 97, 160, 142, 38, 146, 69, 152, 206, 114, 64, 78,
+    ],
+    [
+        236, 180, 8, 178, 144, 171, 41, 32, 230, 54, 17, 239, 30, 140, 169, 100, 174, 187, 102,
+        234, 87, 57, 241, 157, 36, 185, 32, 148, 242, 142, 68, 248,
+    ],
+    [
+        41, 75, 249, 120, 94, 19, 145, 210, 77, 82, 171, 249, 21, 99, 106, 115, 189, 170, 18, 237,
+        41, 232, 94, 33, 218, 225, 76, 9, 208, 242, 227, 75,
+    ],
+    [
+        207, 126, 55, 169, 52, 104, 62, 222, 199, 149, 227, 82, 157, 184, 250, 192, 134, 53, 25,
+        194, 65, 155, 162, 76, 15, 110, 78, 250, 134, 236, 125, 26,
+    ],
+    [
+        114, 206, 72, 204, 169, 188, 116, 63, 237, 132, 160, 189, 192, 10, 210, 197, 181, 64, 50,
+        56, 73, 201, 130, 246, 113, 169, 189, 141, 82, 209, 87, 25,
+    ],
+    [
+        14, 45, 125, 11, 105, 95, 220, 217, 220, 108, 84, 49, 58, 230, 82, 33, 131, 117, 195, 53,
+        141, 52, 173, 227, 220, 196, 157, 218, 5, 192, 219, 16,
+    ],
+    [
+        160, 167, 134, 167, 29, 36, 0, 122, 35, 245, 206, 33, 230, 153, 122, 48, 91, 174, 239, 175,
+        52, 137, 55, 168, 183, 73, 116, 203, 3, 156, 16, 200,
+    ],
+    [
+        6, 40, 203, 100, 150, 25, 62, 106, 85, 236, 228, 168, 36, 3, 132, 225, 66, 39, 62, 235,
+        156, 196, 189, 174, 104, 15, 151, 31, 209, 146, 159, 16,
+    ],
+    [
+        44, 59, 131, 58, 60, 169, 214, 240, 32, 85, 135, 22, 85, 221, 24, 240, 3, 162, 121, 248,
+        74, 51, 75, 246, 5, 8, 153, 169, 188, 28, 237, 195,
+    ],
+    [
+        26, 108, 94, 159, 216, 153, 239, 202, 100, 194, 172, 166, 36, 52, 52, 180, 35, 215, 145,
+        249, 42, 131, 156, 12, 146, 206, 10, 90, 81, 160, 114, 52,
+    ],
+    [
+        169, 148, 43, 15, 171, 223, 155, 243, 34, 65, 248, 36, 94, 178, 103, 55, 255, 189, 145,
+        130, 42, 196, 132, 174, 133, 127, 195, 93, 33, 251, 101, 47,
+    ],
+    [
+        126, 66, 6, 161, 16, 168, 114, 28, 198, 132, 85, 195, 84, 0, 176, 86, 37, 19, 152, 223,
+        207, 152, 176, 74, 238, 225, 149, 73, 191, 210, 18, 47,
+    ],
+    [
+        191, 180, 41, 191, 235, 179, 170, 39, 0, 18, 216, 6, 56, 150, 210, 5, 191, 216, 206, 17,
+        110, 74, 48, 67, 53, 189, 89, 233, 71, 31, 159, 204,
+    ],
+    [
+        226, 47, 168, 255, 242, 140, 251, 31, 54, 100, 56, 44, 237, 109, 63, 29, 99, 78, 242, 133,
+        28, 16, 177, 220, 225, 140, 87, 201, 87, 152, 91, 249,
+    ],
+    [
+        210, 155, 242, 49, 227, 10, 251, 92, 209, 188, 1, 210, 20, 233, 5, 198, 12, 135, 83, 59,
+        249, 103, 169, 72, 119, 179, 132, 59, 176, 189, 239, 93,
+    ],
+    [
+        20, 109, 87, 247, 11, 203, 88, 4, 101, 17, 102, 25, 53, 154, 53, 236, 227, 100, 241, 126,
+        141, 86, 82, 75, 94, 238, 139, 246, 184, 235, 253, 246,
+    ],
+    [
+        205, 188, 7, 89, 232, 71, 249, 107, 235, 0, 12, 60, 197, 30, 48, 248, 178, 204, 172, 93,
+        204, 92, 64, 159, 234, 147, 138, 51, 69, 248, 182, 3,
+    ],
+    [
+        134, 228, 176, 121, 35, 38, 195, 89, 149, 241, 86, 59, 13, 143, 153, 252, 56, 176, 52, 138,
+        53, 110, 20, 156, 4, 178, 120, 61, 91, 212, 207, 184,
+    ],
+    [
+        69, 150, 81, 112, 212, 213, 175, 219, 199, 221, 84, 239, 59, 235, 4, 78, 58, 97, 189, 193,
+        169, 106, 77, 49, 190, 170, 238, 107, 117, 163, 240, 99,
+    ],
+    [
+        180, 96, 62, 70, 240, 222, 152, 142, 98, 255, 39, 182, 159, 77, 57, 58, 24, 245, 237, 49,
+        234, 38, 95, 238, 105, 231, 61, 196, 238, 221, 201, 2,
+    ],
+    [
+        179, 148, 178, 159, 254, 65, 117, 52, 139, 137, 44, 137, 46, 14, 238, 94, 86, 92, 131, 235,
+        190, 36, 125, 225, 124, 217, 191, 153, 157, 117, 210, 183,
+    ],
+    [
+        173, 68, 227, 29, 180, 117, 78, 1, 175, 187, 32, 184, 172, 235, 52, 206, 94, 241, 149, 169,
+        112, 26, 113, 21, 50, 96, 145, 196, 254, 219, 90, 68,
+    ],
+    [
+        11, 242, 202, 138, 162, 54, 195, 93, 62, 123, 208, 186, 151, 200, 231, 114, 25, 68, 98,
+        201, 11, 91, 182, 202, 234, 71, 116, 228, 16, 14, 149, 239,
+    ],
+    [
+        222, 10, 164, 244, 20, 6, 101, 23, 185, 16, 134, 45, 128, 46, 97, 108, 65, 139, 242, 65,
+        80, 148, 12, 59, 116, 180, 110, 106, 78, 132, 177, 90,
+    ],
+    [
+        83, 104, 207, 0, 228, 49, 144, 240, 74, 149, 154, 43, 253, 135, 214, 72, 176, 43, 180, 73,
+        74, 0, 183, 85, 193, 25, 141, 163, 80, 224, 76, 219,
+    ],
+    [
+        34, 60, 28, 159, 22, 223, 166, 91, 220, 152, 189, 47, 244, 25, 182, 241, 196, 117, 150,
+        195, 241, 238, 215, 28, 90, 61, 60, 42, 60, 51, 249, 155,
+    ],
+];
+
+/// Hash function used to build a tree. `MerkleTree` and the binary tree of
+/// the helper contract support all of them, its k-ary tree only `Keccak256`.
+#[contracttype]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HashAlgo {
+    Keccak256,
+    Sha256,
+    /// Keccak-256 with RFC 6962 style domain separation: a leaf is stored as
+    /// `keccak256(0x00 || leaf)` and an internal node is
+    /// `keccak256(0x01 || left || right)`, so an internal node can't be
+    /// passed off as a leaf. Gives different roots than `Keccak256`.
+    Keccak256Prefixed,
+}
+
+/// Hash of the nodes of a `MerkleTree`. `HashAlgo` implements it for the
+/// built-in hashes, and other crates can implement it to build a tree with a
+/// hash of their own, e.g. Poseidon, through `insert_with_hasher`,
+/// `root_with_hasher` and `branch_root_with_hasher`.
+pub trait MerkleHasher {
+    /// Returns the node with children `left` and `right`.
+    fn hash_pair(&self, env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32>;
+
+    /// Returns the node with children `left` and `right` at `level`, the
+    /// leaves being level 0, and `position` within that level. Only a hasher
+    /// binding nodes to their position needs more than `hash_pair`, which
+    /// still hashes the subtrees without any leaf.
+    fn hash_node(
+        &self,
+        env: &Env,
+        _level: u32,
+        _position: u64,
+        left: &BytesN<32>,
+        right: &BytesN<32>,
+    ) -> BytesN<32> {
+        return self.hash_pair(env, left, right);
+    }
+
+    /// Returns the node stored for an inserted `leaf`, the leaf itself
+    /// unless the hasher separates leaves from internal nodes.
+    fn hash_leaf(&self, _env: &Env, leaf: &BytesN<32>) -> BytesN<32> {
+        return leaf.clone();
+    }
+
+    /// Returns the root of an empty subtree at every level below `depth`,
+    /// starting at an all zero leaf.
+    fn zero_hashes(&self, env: &Env, depth: u32) -> Vec<BytesN<32>> {
+        let mut _current = BytesN::from_array(env, &[0; 32]);
+        let mut _zeroes = vec![env];
+        for _ in 0..depth {
+            _zeroes.push_back(_current.clone());
+            _current = self.hash_pair(env, &_current, &_current);
+        }
+        return _zeroes;
+    }
+}
+
+/// Keccak-256 binding every node above a leaf to its position, as
+/// `keccak256([level, position, left, right])` with `level` and `position`
+/// encoded as 32-byte big-endian words, so a proof can't be replayed at
+/// another index. Subtrees without any leaf are the `Keccak256` zero hashes.
+/// This changes the root, so the tree must be built with `insert_with_hasher`
+/// and its proofs checked with `position_bound_branch_root`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PositionBound;
+
+impl MerkleHasher for PositionBound {
+    fn hash_pair(&self, env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+        return MerkleTree::keccak256_pair(env, left, right);
+    }
+
+    fn hash_node(
+        &self,
+        env: &Env,
+        _level: u32,
+        _position: u64,
+        left: &BytesN<32>,
+        right: &BytesN<32>,
+    ) -> BytesN<32> {
+        let _vec = vec![
+            env,
+            MerkleTree::to_word(_level as u64),
+            MerkleTree::to_word(_position),
+            left.to_array(),
+            right.to_array(),
+        ];
+        return BytesN::from_array(env, &MerkleTree::keccak256(_vec));
+    }
+
+    fn zero_hashes(&self, env: &Env, depth: u32) -> Vec<BytesN<32>> {
+        return MerkleTree::zero_hashes_at(env.clone(), HashAlgo::Keccak256, depth);
+    }
+}
+
+impl MerkleHasher for HashAlgo {
+    fn hash_pair(&self, env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+        return MerkleTree::hash_pair(env, *self, left, right);
+    }
+
+    fn hash_leaf(&self, env: &Env, leaf: &BytesN<32>) -> BytesN<32> {
+        return match self {
+            HashAlgo::Keccak256Prefixed => MerkleTree::prefixed_keccak256(env, 0x00, &[leaf]),
+            _ => leaf.clone(),
+        };
+    }
+
+    fn zero_hashes(&self, env: &Env, depth: u32) -> Vec<BytesN<32>> {
+        return MerkleTree::zero_hashes_at(env.clone(), *self, depth);
+    }
 }
 
-const TREE_DEPTH: usize = 32;
-const MAX_LEAVES: u64 = u64::pow(2, TREE_DEPTH as u32) - 1;
+/// Byte order of a count serialized into a hash, as a 32-byte word holding
+/// the `u64` in its last 8 bytes for `Big` and its first 8 bytes for `Little`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Endianness {
+    Big,
+    Little,
+}
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct MerkleTree {
     branch: Vec<BytesN<32>>,
-    count: u32,
+    count: u64,
+    /// Levels of the tree, from 1 to `TREE_DEPTH`.
+    depth: u32,
+    /// Hash of every node, the zero hashes included.
+    hash_algo: HashAlgo,
 }
 
 impl MerkleTree {
+    /// @notice Returns an empty tree of depth `TREE_DEPTH`
+    pub fn new(env: &Env) -> MerkleTree {
+        return Self::with_depth(env, TREE_DEPTH as u32);
+    }
+
+    /**
+     * @notice Returns an empty tree of depth `_depth`, holding up to
+     * `2^_depth` leaves
+     * @dev Reverts if `_depth` is 0 or above `TREE_DEPTH`
+     * @param _depth Levels of the tree
+     **/
+    pub fn with_depth(env: &Env, _depth: u32) -> MerkleTree {
+        return Self::with_hash_algo(env, _depth, HashAlgo::Keccak256);
+    }
+
+    /**
+     * @notice Returns an empty tree of depth `_depth` hashing its nodes with
+     * `_hash_algo`
+     * @dev Reverts if `_depth` is 0 or above `TREE_DEPTH`
+     * @param _depth Levels of the tree
+     * @param _hash_algo Hash of every node
+     **/
+    pub fn with_hash_algo(env: &Env, _depth: u32, _hash_algo: HashAlgo) -> MerkleTree {
+        assert_with_error!(
+            env,
+            (1..=MAX_SUPPORTED_DEPTH).contains(&_depth),
+            Error::InvalidDepth
+        );
+        return MerkleTree {
+            branch: vec![env],
+            count: 0,
+            depth: _depth,
+            hash_algo: _hash_algo,
+        };
+    }
+
+    pub fn depth(&self) -> u32 {
+        return self.depth;
+    }
+
+    pub fn hash_algo(&self) -> HashAlgo {
+        return self.hash_algo;
+    }
+
+    /**
+     * @notice Returns the frontier, entry `i` being the last complete node of
+     * level `i`, the leaves being level 0. It only grows up to the highest
+     * level reached, entries of levels whose bit is clear in `count` hold
+     * the zero hash of their level, and an entry at `depth` is the root of a
+     * full tree.
+     **/
+    pub fn branch(&self) -> Vec<BytesN<32>> {
+        return self.branch.clone();
+    }
+
+    /// @notice Returns how many leaves the tree can hold
+    pub fn capacity(&self) -> u64 {
+        return 1 << self.depth;
+    }
+
+    /// @notice Returns whether `insert` would revert with `MerkleTreeFull`
+    pub fn is_full(&self) -> bool {
+        return self.count >= self.capacity();
+    }
+
+    /// @notice Returns how many leaves can still be inserted
+    pub fn remaining_capacity(&self) -> u64 {
+        return self.capacity().saturating_sub(self.count);
+    }
+
     pub fn keccak256(items: Vec<[u8; 32]>) -> [u8; 32] {
+        #[cfg(test)]
+        KECCAK_CALLS.with(|calls| calls.set(calls.get() + 1));
+
         let mut hasher = Keccak::v256();
         let mut output: [u8; 32] = [0; 32];
 
@@ -36,27 +670,95 @@ impl MerkleTree {
         return output;
     }
 
+    /// @notice Helper function to calculate the keccak256 hash of raw bytes
+    pub fn keccak256_bytes(data: &Bytes) -> [u8; 32] {
+        let mut hasher = Keccak::v256();
+        let mut output: [u8; 32] = [0; 32];
+        let mut chunk: [u8; 64] = [0; 64];
+
+        let mut start = 0;
+        while start < data.len() {
+            let end = u32::min(start + chunk.len() as u32, data.len());
+            let size = (end - start) as usize;
+            data.slice(start..end).copy_into_slice(&mut chunk[..size]);
+            hasher.update(&chunk[..size]);
+            start = end;
+        }
+
+        hasher.finalize(&mut output);
+        return output;
+    }
+
     /**
      * @notice Inserts `_node` into merkle tree
      * @dev Reverts if tree is full
      * @param _node Element to insert into tree
+     * @return Index `_node` was inserted at, as taken by `branch_root`
      **/
-    pub fn insert(&mut self, env: Env, mut _node: BytesN<32>) {
-        assert_with_error!(
-            &env,
-            (self.count as u64) < MAX_LEAVES,
-            Error::MerkleTreeFull
-        );
+    pub fn insert(&mut self, env: Env, _node: BytesN<32>) -> u32 {
+        let _hash_algo = self.hash_algo;
+        return self.insert_with_hasher(env, &_hash_algo, _node);
+    }
 
-        assert_with_error!(
-            &env,
-            self.branch.len() <= TREE_DEPTH as u32,
-            Error::MerkleTreeInvalidVecSize
-        );
+    /**
+     * @notice Same as `insert` returning the error instead of reverting
+     * @param _node Element to insert into tree
+     * @return Index `_node` was inserted at, `MerkleTreeFull` if the tree is
+     * full or `MerkleTreeInvalidVecSize` if its frontier is too long
+     **/
+    pub fn try_insert(&mut self, env: Env, _node: BytesN<32>) -> Result<u32, Error> {
+        let _hash_algo = self.hash_algo;
+        return self.try_insert_with_hasher(env, &_hash_algo, _node);
+    }
 
+    /**
+     * @notice Same as `insert` hashing the nodes with `_hasher` instead of
+     * `hash_algo`
+     * @dev Every insert and root of the tree must use the same `_hasher`
+     * @param _hasher Hash of every node
+     * @param _node Element to insert into tree
+     * @return Index `_node` was inserted at
+     **/
+    pub fn insert_with_hasher(
+        &mut self,
+        env: Env,
+        _hasher: &impl MerkleHasher,
+        _node: BytesN<32>,
+    ) -> u32 {
+        return match self.try_insert_with_hasher(env.clone(), _hasher, _node) {
+            Ok(index) => index,
+            Err(error) => panic_with_error!(&env, error),
+        };
+    }
+
+    /// @notice Same as `insert_with_hasher` returning the error instead of
+    /// reverting, see `try_insert`
+    pub fn try_insert_with_hasher(
+        &mut self,
+        env: Env,
+        _hasher: &impl MerkleHasher,
+        _node: BytesN<32>,
+    ) -> Result<u32, Error> {
+        if self.count >= self.capacity() {
+            return Err(Error::MerkleTreeFull);
+        }
+        if self.branch.len() > self.depth {
+            return Err(Error::MerkleTreeInvalidVecSize);
+        }
+
+        let mut _node = _hasher.hash_leaf(&env, &_node);
         self.count += 1;
         let mut size = self.count;
-        for i in 0..TREE_DEPTH as u32 {
+        // Levels the new leaf carries through go back to their zero hash, so
+        // the frontier only holds a node where `count` has its bit set
+        let _carries = u32::min(size.trailing_zeros(), self.depth);
+        let _zeroes = if _carries > 0 {
+            _hasher.zero_hashes(&env, _carries)
+        } else {
+            vec![&env]
+        };
+        // The last leaf carries the root of the full tree up to level `depth`
+        for i in 0..=self.depth {
             if (size & 1) == 1 {
                 let item_pos = self.branch.get(i);
 
@@ -65,68 +767,229 @@ impl MerkleTree {
                 } else {
                     self.branch.set(i, _node);
                 }
-                return;
+                return Ok((self.count - 1) as u32);
             }
 
-            let leaf = self.branch.get(i).expect("Error to get leaf");
-
-            let _vec = vec![&env, leaf.to_array(), _node.to_array()];
+            // A left sibling at every even level, unless the stored frontier
+            // lost it
+            let leaf = match self.branch.get(i) {
+                Some(leaf) => leaf,
+                None => return Err(Error::MerkleTreeInvalidVecSize),
+            };
 
-            let output = Self::keccak256(_vec);
-            _node = BytesN::from_array(&env, &output);
+            let _position = (self.count - 1) >> (i + 1);
+            _node = _hasher.hash_node(&env, i + 1, _position, &leaf, &_node);
+            self.branch.set(i, _zeroes.get_unchecked(i));
 
             size /= 2;
         }
         // As the loop should always end prematurely with the `return` statement,
-        // this code should be unreachable. We assert `false` just to be safe.
-        assert!(false);
+        // this code should be unreachable. We panic just to be safe.
+        unreachable!();
+    }
+
+    /**
+     * @notice Inserts every element of `_nodes` in order
+     * @dev Reverts before inserting anything if the tree cannot hold them all
+     * @param _nodes Elements to insert into tree
+     * @return Index the first element was inserted at and the number inserted
+     **/
+    pub fn insert_batch(&mut self, env: Env, _nodes: Vec<BytesN<32>>) -> (u64, u32) {
+        assert_with_error!(
+            &env,
+            _nodes.len() as u64 <= self.remaining_capacity(),
+            Error::MerkleTreeFull
+        );
+
+        let _start = self.count;
+        for node in _nodes.iter() {
+            self.insert(env.clone(), node);
+        }
+        return (_start, _nodes.len());
+    }
+
+    /**
+     * @notice Drops the frontier entries above the highest level `count` can
+     * have filled, which no root or insert reads
+     * @dev The frontier only grows when `count` reaches a new power of two,
+     * so extra entries can only come from state written elsewhere, e.g. with
+     * a larger depth
+     **/
+    pub fn compact(&mut self, _env: Env) {
+        let _levels = u32::min(64 - self.count.leading_zeros(), self.depth + 1);
+        while self.branch.len() > _levels {
+            self.branch.pop_back();
+        }
     }
 
     /**
      * @notice Calculates and returns`_tree`'s current root given array of zero
      * hashes
+     * @dev `_zeroes` must be the zero hash tower of `depth` entries over
+     * `_empty_leaf`, see `check_zero_hashes`, and reverts otherwise
+     * @param _empty_leaf Value of an unfilled leaf
      * @param _zeroes Array of zero hashes
      * @return _current Calculated root of `_tree`
      **/
-    fn root_with_ctx(&self, env: Env, _zeroes: Vec<BytesN<32>>) -> BytesN<32> {
+    #[cfg(feature = "helper-contract")]
+    fn root_with_ctx(
+        &self,
+        env: Env,
+        _empty_leaf: &BytesN<32>,
+        _zeroes: Vec<BytesN<32>>,
+    ) -> BytesN<32> {
+        Self::check_zero_hashes(&env, self.hash_algo, _empty_leaf, &_zeroes);
+        if self.count == self.capacity() {
+            return self.full_root(&env);
+        }
         assert_with_error!(
             &env,
-            self.branch.len() <= TREE_DEPTH as u32 && _zeroes.len() == TREE_DEPTH as u32,
+            self.branch.len() <= self.depth && _zeroes.len() == self.depth,
             Error::MerkleTreeInvalidVecSize
         );
+        return self.subtree_root_with_ctx(env, _zeroes, self.depth);
+    }
+
+    /**
+     * @notice Checks `_zeroes` is the zero hash tower for `_hash_algo` over
+     * `_empty_leaf`, each entry the hash of the previous one with itself
+     * @dev Reverts with `InvalidZeroHashes` otherwise. The built-in tower of
+     * the zero leaf is compared with its table, any other tower is hashed
+     * once per entry
+     * @param _hash_algo Hash of every node
+     * @param _empty_leaf Value of an unfilled leaf, the base of the tower
+     * @param _zeroes Array of zero hashes
+     **/
+    pub fn check_zero_hashes(
+        env: &Env,
+        _hash_algo: HashAlgo,
+        _empty_leaf: &BytesN<32>,
+        _zeroes: &Vec<BytesN<32>>,
+    ) {
+        if _empty_leaf.to_array() == [0; 32]
+            && *_zeroes == Self::zero_hashes_at(env.clone(), _hash_algo, _zeroes.len())
+        {
+            return;
+        }
+
+        let mut _expected = _empty_leaf.clone();
+        for hash in _zeroes.iter() {
+            assert_with_error!(env, hash == _expected, Error::InvalidZeroHashes);
+            _expected = Self::hash_pair(env, _hash_algo, &_expected, &_expected);
+        }
+    }
+
+    /**
+     * @notice Calculates the root of the subtree of height `_level` holding
+     * the next empty leaf, given array of zero hashes
+     * @param _zeroes Array of zero hashes
+     * @param _level Height of the subtree, `depth` gives the tree root
+     * @return _current Calculated root of the subtree
+     **/
+    #[cfg(feature = "helper-contract")]
+    fn subtree_root_with_ctx(&self, env: Env, _zeroes: Vec<BytesN<32>>, _level: u32) -> BytesN<32> {
+        return self.subtree_root_with_hasher(env, &self.hash_algo, _zeroes, _level);
+    }
 
-        let mut _current = BytesN::from_array(&env, &[0; 32]);
+    fn subtree_root_with_hasher(
+        &self,
+        env: Env,
+        _hasher: &impl MerkleHasher,
+        _zeroes: Vec<BytesN<32>>,
+        _level: u32,
+    ) -> BytesN<32> {
+        let mut _current = _zeroes.get_unchecked(0);
         let _index = self.count;
 
-        for i in 0..TREE_DEPTH as u32 {
+        for i in 0.._level {
             let _ith_bit = (_index >> i) & 0x01;
-            let _next = self
-                .branch
-                .get(i)
-                .unwrap_or(BytesN::from_array(&env, &[0; 32]));
+            let _position = _index >> (i + 1);
             if _ith_bit == 1 {
-                let _vec = vec![&env, _next.clone().to_array(), _current.clone().to_array()];
-                let value = Self::keccak256(_vec);
-                _current = BytesN::from_array(&env, &value)
+                let _next = self
+                    .branch
+                    .get(i)
+                    .unwrap_or_else(|| panic_with_error!(&env, Error::MerkleTreeInvalidVecSize));
+                _current = _hasher.hash_node(&env, i + 1, _position, &_next, &_current);
+            } else if _index & ((1 << i) - 1) == 0 {
+                // No leaf below yet, so this is the zero hash of the next level
+                let hash = _zeroes.get_unchecked(i);
+                _current = _hasher.hash_pair(&env, &_current, &hash);
             } else {
                 let hash = _zeroes.get_unchecked(i);
-                let _vec = vec![&env, _current.clone().to_array(), hash.clone().to_array()];
-                let value = Self::keccak256(_vec);
-                _current = BytesN::from_array(&env, &value)
+                _current = _hasher.hash_node(&env, i + 1, _position, &_current, &hash);
             }
         }
         return _current;
     }
 
+    /// @notice Returns the number of leaves inserted so far, not the capacity
+    pub fn count(&self) -> u64 {
+        return self.count;
+    }
+
+    /// @notice Returns whether the slot at `_index` has been filled, only
+    /// those leaves can be proven against the current root
+    pub fn contains_index(&self, _index: u32) -> bool {
+        return (_index as u64) < self.count;
+    }
+
     /// @notice Calculates and returns`_tree`'s current root
     pub fn root(&self, env: Env) -> BytesN<32> {
+        return self.root_with_hasher(env, &self.hash_algo);
+    }
+
+    /// @notice Same as `root` for a tree built with `insert_with_hasher`
+    pub fn root_with_hasher(&self, env: Env, _hasher: &impl MerkleHasher) -> BytesN<32> {
+        if self.count == self.capacity() {
+            return self.full_root(&env);
+        }
+        assert_with_error!(
+            &env,
+            self.branch.len() <= self.depth,
+            Error::MerkleTreeInvalidVecSize
+        );
+        // The zero hashes of a hasher are trusted, no need to check them like
+        // `root_with_ctx`
+        let _zeroes = _hasher.zero_hashes(&env, self.depth);
+        return self.subtree_root_with_hasher(env, _hasher, _zeroes, self.depth);
+    }
+
+    /**
+     * @notice Checks whether `other` has the same root, whatever its frontier
+     * holds past the nodes the root reads, e.g. after `compact` or a
+     * migration between storage formats
+     * @param other Tree to compare with
+     * @return Whether both roots are equal
+     **/
+    pub fn has_same_root(&self, env: Env, other: &MerkleTree) -> bool {
+        return self.root(env.clone()) == other.root(env);
+    }
+
+    /// @notice Returns the root of a full tree, stored above the top level
+    fn full_root(&self, env: &Env) -> BytesN<32> {
+        return self
+            .branch
+            .get(self.depth)
+            .unwrap_or_else(|| panic_with_error!(env, Error::MerkleTreeInvalidVecSize));
+    }
+
+    /// @notice Returns the root of a tree without any leaf
+    pub fn empty_root(env: Env) -> BytesN<32> {
+        let _empty = MerkleTree::new(&env);
+        return _empty.root(env);
+    }
+
+    /// @notice Returns the root of a tree holding only `_item`, at index 0
+    pub fn root_of_single(env: Env, _item: BytesN<32>) -> BytesN<32> {
         let _zeroes = Self::zero_hashes(env.clone());
-        return Self::root_with_ctx(&self, env.clone(), _zeroes);
+        return Self::branch_root(env, _item, _zeroes, LeafIndex(0));
     }
 
     /**
      * @notice Calculates and returns the merkle root for the given leaf
      * `_item`, a merkle branch, and the index of `_item` in the tree.
+     * @dev Reverts if `_index` is not below `2^TREE_DEPTH` or `_branch` does
+     * not hold exactly `TREE_DEPTH` siblings
      * @param _item Merkle leaf
      * @param _branch Merkle proof
      * @param _index Index of `_item` in tree
@@ -136,381 +999,2776 @@ impl MerkleTree {
         env: Env,
         _item: BytesN<32>,
         _branch: Vec<BytesN<32>>,
-        _index: u64,
+        _index: LeafIndex,
     ) -> BytesN<32> {
-        let mut _current = _item;
+        return Self::branch_root_at_depth(env, TREE_DEPTH as u32, _item, _branch, _index);
+    }
 
-        for i in 0..TREE_DEPTH as u32 {
-            let _ith_bit = (_index >> i) & 0x01;
-            let _next = _branch.get(i).unwrap_or(BytesN::from_array(&env, &[0; 32]));
-            if _ith_bit == 1 {
-                let _vec = vec![&env, _next.to_array(), _current.to_array()];
-                let value = Self::keccak256(_vec);
-                _current = BytesN::from_array(&env, &value)
-            } else {
-                let _vec = vec![&env, _current.to_array(), _next.to_array()];
-                let value = Self::keccak256(_vec);
-                _current = BytesN::from_array(&env, &value)
-            }
-        }
-        return _current;
+    /**
+     * @notice Same as `branch_root` for a tree of depth `_depth`, `_branch`
+     * holding `_depth` siblings
+     * @param _depth Levels of the tree, the length of a full `_branch`
+     * @param _item Merkle leaf
+     * @param _branch Merkle proof
+     * @param _index Index of `_item` in tree
+     * @return Calculated merkle root
+     **/
+    pub fn branch_root_at_depth(
+        env: Env,
+        _depth: u32,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+    ) -> BytesN<32> {
+        return Self::branch_root_with(env, HashAlgo::Keccak256, _depth, _item, _branch, _index);
     }
 
-    /// @notice Returns array of TREE_DEPTH zero hashes
-    /// @return _zeroes Array of TREE_DEPTH zero hashes
-    fn zero_hashes(env: Env) -> Vec<BytesN<32>> {
-        let mut _zeroes = vec![&env];
-        _zeroes.insert(0, BytesN::from_array(&env, &[0; 32]));
-        _zeroes.insert(
-            1,
-            BytesN::from_array(
-                &env,
-                &[
-                    173, 50, 40, 182, 118, 247, 211, 205, 66, 132, 165, 68, 63, 23, 241, 150, 43,
-                    54, 228, 145, 179, 10, 64, 178, 64, 88, 73, 229, 151, 186, 95, 181,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            2,
-            BytesN::from_array(
-                &env,
-                &[
-                    180, 193, 25, 81, 149, 124, 111, 143, 100, 44, 74, 246, 28, 214, 178, 70, 64,
-                    254, 198, 220, 127, 198, 7, 238, 130, 6, 169, 158, 146, 65, 13, 48,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            3,
-            BytesN::from_array(
-                &env,
-                &[
-                    33, 221, 185, 163, 86, 129, 92, 63, 172, 16, 38, 182, 222, 197, 223, 49, 36,
-                    175, 186, 219, 72, 92, 155, 165, 163, 227, 57, 138, 4, 183, 186, 133,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            4,
-            BytesN::from_array(
-                &env,
-                &[
-                    229, 135, 105, 179, 42, 27, 234, 241, 234, 39, 55, 90, 68, 9, 90, 13, 31, 182,
-                    100, 206, 45, 211, 88, 231, 252, 191, 183, 140, 38, 161, 147, 68,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            5,
-            BytesN::from_array(
-                &env,
-                &[
-                    14, 176, 30, 191, 201, 237, 39, 80, 12, 212, 223, 201, 121, 39, 45, 31, 9, 19,
-                    204, 159, 102, 84, 13, 126, 128, 5, 129, 17, 9, 225, 207, 45,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            6,
-            BytesN::from_array(
-                &env,
-                &[
-                    136, 124, 34, 189, 135, 80, 211, 64, 22, 172, 60, 102, 181, 255, 16, 45, 172,
-                    221, 115, 246, 176, 20, 231, 16, 181, 30, 128, 34, 175, 154, 25, 104,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            7,
-            BytesN::from_array(
-                &env,
-                &[
-                    255, 215, 1, 87, 228, 128, 99, 252, 51, 201, 122, 5, 15, 127, 100, 2, 51, 191,
-                    100, 108, 201, 141, 149, 36, 198, 185, 43, 207, 58, 181, 111, 131,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            8,
-            BytesN::from_array(
-                &env,
-                &[
-                    152, 103, 204, 95, 127, 25, 107, 147, 186, 225, 226, 126, 99, 32, 116, 36, 69,
-                    210, 144, 242, 38, 56, 39, 73, 139, 84, 254, 197, 57, 247, 86, 175,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            9,
-            BytesN::from_array(
-                &env,
-                &[
-                    206, 250, 212, 229, 8, 192, 152, 185, 167, 225, 216, 254, 177, 153, 85, 251, 2,
-                    186, 150, 117, 88, 80, 120, 113, 9, 105, 211, 68, 15, 80, 84, 224,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            10,
-            BytesN::from_array(
-                &env,
-                &[
-                    249, 220, 62, 127, 224, 22, 224, 80, 239, 242, 96, 51, 79, 24, 165, 212, 254,
-                    57, 29, 130, 9, 35, 25, 245, 150, 79, 46, 46, 183, 193, 195, 165,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            11,
-            BytesN::from_array(
-                &env,
-                &[
-                    248, 177, 58, 73, 226, 130, 246, 9, 195, 23, 168, 51, 251, 141, 151, 109, 17,
-                    81, 124, 87, 29, 18, 33, 162, 101, 210, 90, 247, 120, 236, 248, 146,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            12,
-            BytesN::from_array(
-                &env,
-                &[
-                    52, 144, 198, 206, 235, 69, 10, 236, 220, 130, 226, 130, 147, 3, 29, 16, 199,
-                    215, 59, 248, 94, 87, 191, 4, 26, 151, 54, 10, 162, 197, 217, 156,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            13,
-            BytesN::from_array(
-                &env,
-                &[
-                    193, 223, 130, 217, 196, 184, 116, 19, 234, 226, 239, 4, 143, 148, 180, 211,
-                    85, 76, 234, 115, 217, 43, 15, 122, 249, 110, 2, 113, 198, 145, 226, 187,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            14,
-            BytesN::from_array(
-                &env,
-                &[
-                    92, 103, 173, 215, 198, 202, 243, 2, 37, 106, 222, 223, 122, 177, 20, 218, 10,
-                    207, 232, 112, 212, 73, 163, 164, 137, 247, 129, 214, 89, 232, 190, 204,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            15,
-            BytesN::from_array(
-                &env,
-                &[
-                    218, 123, 206, 159, 78, 134, 24, 182, 189, 47, 65, 50, 206, 121, 140, 220, 122,
-                    96, 231, 225, 70, 10, 114, 153, 227, 198, 52, 42, 87, 150, 38, 210,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            16,
-            BytesN::from_array(
-                &env,
-                &[
-                    39, 51, 229, 15, 82, 110, 194, 250, 25, 162, 43, 49, 232, 237, 80, 242, 60,
-                    209, 253, 249, 76, 145, 84, 237, 58, 118, 9, 162, 241, 255, 152, 31,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            17,
-            BytesN::from_array(
-                &env,
-                &[
-                    225, 211, 181, 200, 7, 178, 129, 228, 104, 60, 198, 214, 49, 92, 249, 91, 154,
-                    222, 134, 65, 222, 252, 179, 35, 114, 241, 193, 38, 227, 152, 239, 122,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            18,
-            BytesN::from_array(
-                &env,
-                &[
-                    90, 45, 206, 10, 138, 127, 104, 187, 116, 86, 15, 143, 113, 131, 124, 44, 46,
-                    187, 203, 247, 255, 251, 66, 174, 24, 150, 241, 63, 124, 116, 121, 160,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            19,
-            BytesN::from_array(
-                &env,
-                &[
-                    180, 106, 40, 182, 245, 85, 64, 248, 148, 68, 246, 61, 224, 55, 142, 61, 18,
-                    27, 224, 158, 6, 204, 157, 237, 28, 32, 230, 88, 118, 211, 106, 160,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            20,
-            BytesN::from_array(
-                &env,
-                &[
-                    198, 94, 150, 69, 100, 71, 134, 182, 32, 226, 221, 42, 214, 72, 221, 252, 191,
-                    74, 126, 91, 26, 58, 78, 207, 231, 246, 70, 103, 163, 240, 183, 226,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            21,
-            BytesN::from_array(
-                &env,
-                &[
-                    244, 65, 133, 136, 237, 53, 162, 69, 140, 255, 235, 57, 185, 61, 38, 241, 141,
-                    42, 177, 59, 220, 230, 174, 229, 142, 123, 153, 53, 158, 194, 223, 217,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            22,
-            BytesN::from_array(
-                &env,
-                &[
-                    90, 156, 22, 220, 0, 214, 239, 24, 183, 147, 58, 111, 141, 198, 92, 203, 85,
-                    102, 113, 56, 119, 111, 125, 234, 16, 16, 112, 220, 135, 150, 227, 119,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            23,
-            BytesN::from_array(
-                &env,
-                &[
-                    77, 248, 79, 64, 174, 12, 130, 41, 208, 214, 6, 158, 92, 143, 57, 167, 194,
-                    153, 103, 122, 9, 211, 103, 252, 123, 5, 227, 188, 56, 14, 230, 82,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            24,
-            BytesN::from_array(
-                &env,
-                &[
-                    205, 199, 37, 149, 247, 76, 123, 16, 67, 208, 225, 255, 186, 183, 52, 100, 140,
-                    131, 141, 251, 5, 39, 217, 113, 182, 2, 188, 33, 108, 150, 25, 239,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            25,
-            BytesN::from_array(
-                &env,
-                &[
-                    10, 191, 90, 201, 116, 161, 237, 87, 244, 5, 10, 165, 16, 221, 156, 116, 245,
-                    8, 39, 123, 57, 215, 151, 59, 178, 223, 204, 197, 238, 176, 97, 141,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            26,
-            BytesN::from_array(
-                &env,
-                &[
-                    184, 205, 116, 4, 111, 243, 55, 240, 167, 191, 44, 142, 3, 225, 15, 100, 44,
-                    24, 134, 121, 141, 113, 128, 106, 177, 232, 136, 217, 229, 238, 135, 208,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            27,
-            BytesN::from_array(
-                &env,
-                &[
-                    131, 140, 86, 85, 203, 33, 198, 203, 131, 49, 59, 90, 99, 17, 117, 223, 244,
-                    150, 55, 114, 204, 233, 16, 129, 136, 179, 74, 200, 124, 129, 196, 30,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            28,
-            BytesN::from_array(
-                &env,
-                &[
-                    102, 46, 228, 221, 45, 215, 178, 188, 112, 121, 97, 177, 230, 70, 196, 4, 118,
-                    105, 220, 182, 88, 79, 13, 141, 119, 13, 175, 93, 126, 125, 235, 46,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            29,
-            BytesN::from_array(
-                &env,
-                &[
-                    56, 138, 178, 14, 37, 115, 209, 113, 168, 129, 8, 231, 157, 130, 14, 152, 242,
-                    108, 11, 132, 170, 139, 47, 74, 164, 150, 141, 187, 129, 142, 163, 34,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            30,
-            BytesN::from_array(
-                &env,
-                &[
-                    147, 35, 124, 80, 186, 117, 238, 72, 95, 76, 34, 173, 242, 247, 65, 64, 11,
-                    223, 141, 106, 156, 199, 223, 126, 202, 229, 118, 34, 22, 101, 215, 53,
-                ],
-            ),
-        );
-        _zeroes.insert(
-            31,
-            BytesN::from_array(
-                &env,
-                &[
-                    132, 72, 129, 139, 180, 174, 69, 98, 132, 158, 148, 158, 23, 172, 22, 224, 190,
-                    22, 104, 142, 21, 107, 92, 241, 94, 9, 140, 98, 124, 0, 86, 169,
-                ],
-            ),
-        );
+    /**
+     * @notice Same as `branch_root` for a tree of depth `_depth` hashing its
+     * nodes with `_hash_algo`
+     * @dev Reverts if `_index` is not below `2^_depth` or `_branch` does not
+     * hold exactly `_depth` siblings
+     * @param _hash_algo Hash of every node
+     * @param _depth Levels of the tree, the length of a full `_branch`
+     * @param _item Merkle leaf
+     * @param _branch Merkle proof
+     * @param _index Index of `_item` in tree
+     * @return Calculated merkle root
+     **/
+    pub fn branch_root_with(
+        env: Env,
+        _hash_algo: HashAlgo,
+        _depth: u32,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+    ) -> BytesN<32> {
+        return Self::branch_root_with_hasher(env, &_hash_algo, _depth, _item, _branch, _index);
+    }
 
-        return _zeroes;
+    /// @notice Same as `branch_root_with` for a tree built with
+    /// `insert_with_hasher`
+    pub fn branch_root_with_hasher(
+        env: Env,
+        _hasher: &impl MerkleHasher,
+        _depth: u32,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+    ) -> BytesN<32> {
+        let _index = u64::from(_index);
+        assert_with_error!(&env, _index < 1 << _depth, Error::MerkleTreeIndexOutOfRange);
+        assert_with_error!(
+            &env,
+            _branch.len() == _depth,
+            Error::MerkleTreeInvalidVecSize
+        );
+        let _item = _hasher.hash_leaf(&env, &_item);
+        return Self::reduce_with(env, _hasher, _item, _branch, _index);
     }
-}
 
-const TREE: Symbol = symbol_short!("TREE");
+    /**
+     * @notice Folds `_siblings` into a root, starting from `_leaf`
+     * @dev Unlike `branch_root`, `_siblings` can have any length: the root
+     * is `_siblings.len()` levels above `_leaf`
+     * @param _leaf Node at the bottom of the path
+     * @param _siblings Sibling at every level, lowest first
+     * @param _path_bits Bit `i` set when the node at level `i` is a right child
+     * @return Calculated root
+     **/
+    pub fn reduce(
+        env: Env,
+        _leaf: BytesN<32>,
+        _siblings: Vec<BytesN<32>>,
+        _path_bits: u64,
+    ) -> BytesN<32> {
+        return Self::reduce_with(env, &HashAlgo::Keccak256, _leaf, _siblings, _path_bits);
+    }
 
-/**
- * This a basic helper contract used to assist with tests.
- */
-#[contract]
-pub struct Contract;
+    /// @notice Same as `reduce` hashing the nodes with `_hasher`
+    pub fn reduce_with(
+        env: Env,
+        _hasher: &impl MerkleHasher,
+        _leaf: BytesN<32>,
+        _siblings: Vec<BytesN<32>>,
+        _path_bits: u64,
+    ) -> BytesN<32> {
+        let mut _current = _leaf;
 
-#[contractimpl]
-impl Contract {
-    pub fn get_tree(env: Env) -> MerkleTree {
-        //let array = [BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32]),BytesN::from_array(&env, &[0;32])];
-        return env.storage().instance().get(&TREE).unwrap_or(MerkleTree {
+        for (i, _next) in _siblings.iter().enumerate() {
+            let _level = i as u32 + 1;
+            let _position = _path_bits >> _level;
+            if (_path_bits >> i) & 0x01 == 1 {
+                _current = _hasher.hash_node(&env, _level, _position, &_next, &_current);
+            } else {
+                _current = _hasher.hash_node(&env, _level, _position, &_current, &_next);
+            }
+        }
+        return _current;
+    }
+
+    /**
+     * @notice Checks `_item` against a given root, e.g. a past root of the
+     * tree, with the same checks as `branch_root`
+     * @param _item Merkle leaf
+     * @param _branch Merkle proof
+     * @param _index Index of `_item` in tree
+     * @param _root Root the proof must lead to
+     * @return Whether the proof is valid
+     **/
+    pub fn verify_against(
+        env: Env,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+        _root: BytesN<32>,
+    ) -> bool {
+        return Self::branch_root(env, _item, _branch, _index) == _root;
+    }
+
+    /**
+     * @notice Calculates the nodes on the path from `_item` to the root,
+     * the same way `branch_root` does
+     * @param _item Merkle leaf
+     * @param _branch Merkle proof
+     * @param _index Index of `_item` in tree
+     * @return Node at every level, from `_item` at level 0 to the root at
+     * level `TREE_DEPTH`
+     **/
+    pub fn branch_path(
+        env: Env,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+    ) -> Vec<BytesN<32>> {
+        return Self::branch_path_with(
+            env,
+            HashAlgo::Keccak256,
+            TREE_DEPTH as u32,
+            _item,
+            _branch,
+            _index,
+        );
+    }
+
+    /**
+     * @notice Same as `branch_path` for a tree of depth `_depth` hashing its
+     * nodes with `_hash_algo`
+     * @param _hash_algo Hash of every node
+     * @param _depth Levels of the tree
+     * @param _item Merkle leaf
+     * @param _branch Merkle proof
+     * @param _index Index of `_item` in tree
+     * @return Node at every level, from `_item` at level 0 to the root at
+     * level `_depth`
+     **/
+    pub fn branch_path_with(
+        env: Env,
+        _hash_algo: HashAlgo,
+        _depth: u32,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+    ) -> Vec<BytesN<32>> {
+        let _index = u64::from(_index);
+        let mut _path = vec![&env, _item.clone()];
+        let mut _current = _hash_algo.hash_leaf(&env, &_item);
+
+        for i in 0.._depth {
+            let _ith_bit = (_index >> i) & 0x01;
+            let _next = _branch.get(i).unwrap_or(BytesN::from_array(&env, &[0; 32]));
+            _current = if _ith_bit == 1 {
+                Self::hash_pair(&env, _hash_algo, &_next, &_current)
+            } else {
+                Self::hash_pair(&env, _hash_algo, &_current, &_next)
+            };
+            _path.push_back(_current.clone());
+        }
+        return _path;
+    }
+
+    /**
+     * @notice Calculates and returns the position-bound merkle root for the
+     * given leaf `_item`, a merkle branch, and the index of `_item` in the tree.
+     * @dev Same as `branch_root_with_hasher` with `PositionBound`, for a tree
+     * built with `insert_with_hasher` and the same hasher. This yields a
+     * different root than `branch_root`, so proofs and roots must both be
+     * generated with the same hashing. Reverts if `_index` is not below
+     * `2^_depth` or `_branch` does not hold exactly `_depth` siblings
+     * @param _depth Levels of the tree, the length of a full `_branch`
+     * @param _item Merkle leaf
+     * @param _branch Merkle proof
+     * @param _index Index of `_item` in tree
+     * @return Calculated position-bound merkle root
+     **/
+    pub fn position_bound_branch_root(
+        env: Env,
+        _depth: u32,
+        _item: BytesN<32>,
+        _branch: Vec<BytesN<32>>,
+        _index: LeafIndex,
+    ) -> BytesN<32> {
+        return Self::branch_root_with_hasher(env, &PositionBound, _depth, _item, _branch, _index);
+    }
+
+    /**
+     * @notice Calculates the root a tree of depth `TREE_DEPTH` has after
+     * inserting `_leaves`, without any stored state
+     * @dev Same as `insert` for every leaf then `root`, e.g. to rebuild a past
+     * root from the first leaves of a tree
+     * @param _leaves Leaves of the tree, in insertion order
+     * @return Calculated root
+     **/
+    pub fn root_from_leaves(env: Env, _leaves: Vec<BytesN<32>>) -> BytesN<32> {
+        let mut _tree = MerkleTree::new(&env);
+        _tree.insert_batch(env.clone(), _leaves);
+        return _tree.root(env);
+    }
+
+    /**
+     * @notice Calculates the root of a standalone tree built from `_leaves`,
+     * padded with zero leaves up to the next power of two
+     * @dev Unlike `root` this tree is only as deep as `_leaves` requires, a
+     * single leaf is its own root
+     * @param _leaves Leaves of the tree, must not be empty
+     * @return Calculated root
+     **/
+    pub fn padded_root(env: Env, _leaves: Vec<BytesN<32>>) -> BytesN<32> {
+        assert_with_error!(&env, !_leaves.is_empty(), Error::InvalidRange);
+
+        let mut _level = _leaves;
+        let _zero = BytesN::from_array(&env, &[0; 32]);
+        while _level.len() > 1 {
+            let mut _next = vec![&env];
+            for i in (0.._level.len()).step_by(2) {
+                let left = _level.get_unchecked(i);
+                let right = _level.get(i + 1).unwrap_or(_zero.clone());
+                _next.push_back(Self::keccak256_pair(&env, &left, &right));
+            }
+            _level = _next;
+        }
+        return _level.get_unchecked(0);
+    }
+
+    /**
+     * @notice Calculates a Bitcoin style root of `_leaves`, where a level with
+     * an odd count duplicates its rightmost node and nodes are hashed with
+     * double SHA-256
+     * @dev This is a distinct, non incremental computation, it needs every
+     * leaf and has nothing in common with the tree root. Leaves are txids in
+     * internal byte order, the reverse of how explorers display them
+     * @param _leaves Leaves of the tree, must not be empty
+     * @return Calculated root, in internal byte order
+     **/
+    pub fn bitcoin_root(env: Env, _leaves: Vec<BytesN<32>>) -> BytesN<32> {
+        assert_with_error!(&env, !_leaves.is_empty(), Error::InvalidRange);
+
+        let mut _level = _leaves;
+        while _level.len() > 1 {
+            let mut _next = vec![&env];
+            for i in (0.._level.len()).step_by(2) {
+                let left = _level.get_unchecked(i);
+                let right = _level.get(i + 1).unwrap_or(left.clone());
+                let mut _pair = Bytes::from_array(&env, &left.to_array());
+                _pair.append(&Bytes::from_array(&env, &right.to_array()));
+                _next.push_back(Self::double_sha256(&env, _pair));
+            }
+            _level = _next;
+        }
+        return _level.get_unchecked(0);
+    }
+
+    /**
+     * @notice Calculates the root of a tree holding `_leaves` at indices 0 and
+     * up, like `root` does but hashing every node with SHA-256
+     * @param _leaves Leaves of the tree, in insertion order
+     * @return Calculated root
+     **/
+    pub fn sha256_root(env: Env, _leaves: Vec<BytesN<32>>) -> BytesN<32> {
+        let mut _level = _leaves;
+        let mut _zero = BytesN::from_array(&env, &[0; 32]);
+        for _ in 0..TREE_DEPTH {
+            let mut _next = vec![&env];
+            for i in (0.._level.len()).step_by(2) {
+                let left = _level.get_unchecked(i);
+                let right = _level.get(i + 1).unwrap_or(_zero.clone());
+                _next.push_back(Self::sha256_pair(&env, &left, &right));
+            }
+            _level = _next;
+            _zero = Self::sha256_pair(&env, &_zero, &_zero);
+        }
+        return _level.get(0).unwrap_or(_zero);
+    }
+
+    /// @notice Hashes two nodes into their parent with `_hash_algo`
+    pub fn hash_pair(
+        env: &Env,
+        _hash_algo: HashAlgo,
+        left: &BytesN<32>,
+        right: &BytesN<32>,
+    ) -> BytesN<32> {
+        return match _hash_algo {
+            HashAlgo::Keccak256 => Self::keccak256_pair(env, left, right),
+            HashAlgo::Sha256 => Self::sha256_pair(env, left, right),
+            HashAlgo::Keccak256Prefixed => Self::prefixed_keccak256(env, 0x01, &[left, right]),
+        };
+    }
+
+    /// @notice Hashes `_prefix` followed by every node of `_nodes`
+    fn prefixed_keccak256(env: &Env, _prefix: u8, _nodes: &[&BytesN<32>]) -> BytesN<32> {
+        let mut _bytes = Bytes::from_array(env, &[_prefix]);
+        for node in _nodes {
+            _bytes.append(&Bytes::from_array(env, &node.to_array()));
+        }
+        return BytesN::from_array(env, &Self::keccak256_bytes(&_bytes));
+    }
+
+    /// @notice Hashes two nodes into their parent, `keccak256(left || right)`
+    /// as every node of a `Keccak256` tree
+    pub fn keccak256_pair(env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+        let _vec = vec![env, left.to_array(), right.to_array()];
+        return BytesN::from_array(env, &Self::keccak256(_vec));
+    }
+
+    fn sha256_pair(env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+        let mut _pair = Bytes::from_array(env, &left.to_array());
+        _pair.append(&Bytes::from_array(env, &right.to_array()));
+        return env.crypto().sha256(&_pair);
+    }
+
+    /// @notice Hashes `data` twice with SHA-256, as Bitcoin does
+    fn double_sha256(env: &Env, data: Bytes) -> BytesN<32> {
+        let _first = env.crypto().sha256(&data);
+        let _first = Bytes::from_array(env, &_first.to_array());
+        return env.crypto().sha256(&_first);
+    }
+
+    /// @notice Encodes `value` as a 32-byte big-endian word
+    fn to_word(value: u64) -> [u8; 32] {
+        let mut _word = [0; 32];
+        _word[24..].copy_from_slice(&value.to_be_bytes());
+        return _word;
+    }
+
+    /// @notice Returns array of TREE_DEPTH zero hashes built up from
+    /// `_empty_leaf` instead of the all zero leaf
+    pub fn zero_hashes_from(env: Env, _empty_leaf: BytesN<32>) -> Vec<BytesN<32>> {
+        let mut _zeroes = vec![&env, _empty_leaf.clone()];
+        let mut _current = _empty_leaf.to_array();
+        for _ in 1..TREE_DEPTH {
+            _current = Self::keccak256(vec![&env, _current, _current]);
+            _zeroes.push_back(BytesN::from_array(&env, &_current));
+        }
+        return _zeroes;
+    }
+
+    /// @notice Same as `zero_hashes_from` hashing the levels with `_hash_algo`
+    pub fn zero_hashes_from_with(
+        env: Env,
+        _hash_algo: HashAlgo,
+        _empty_leaf: BytesN<32>,
+    ) -> Vec<BytesN<32>> {
+        let mut _zeroes = vec![&env, _empty_leaf.clone()];
+        let mut _current = _empty_leaf;
+        for _ in 1..TREE_DEPTH {
+            _current = Self::hash_pair(&env, _hash_algo, &_current, &_current);
+            _zeroes.push_back(_current.clone());
+        }
+        return _zeroes;
+    }
+
+    /// @notice Returns array of TREE_DEPTH zero hashes
+    /// @return _zeroes Array of TREE_DEPTH zero hashes
+    fn zero_hashes(env: Env) -> Vec<BytesN<32>> {
+        return Self::zero_hashes_at(env, HashAlgo::Keccak256, TREE_DEPTH as u32);
+    }
+
+    /// @notice Returns the `_hash_algo` zero hashes of the `_depth` lowest
+    /// levels
+    fn zero_hashes_at(env: Env, _hash_algo: HashAlgo, _depth: u32) -> Vec<BytesN<32>> {
+        let _table = match _hash_algo {
+            HashAlgo::Keccak256 => &ZERO_HASHES,
+            HashAlgo::Sha256 => &SHA256_ZERO_HASHES,
+            HashAlgo::Keccak256Prefixed => &PREFIXED_ZERO_HASHES,
+        };
+        let mut _zeroes = vec![&env];
+        for hash in _table.iter().take(_depth as usize) {
+            _zeroes.push_back(BytesN::from_array(&env, hash));
+        }
+        return _zeroes;
+    }
+}
+
+/// Incremental tree hashing `fanout` children per node instead of two. It is
+/// `kary_depth(fanout)` levels deep, enough to hold as many leaves as
+/// `MerkleTree`, and each level of a proof holds `fanout - 1` siblings.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KaryTree {
+    fanout: u32,
+    /// Completed children of the unfinished node at every level.
+    branch: Vec<Vec<BytesN<32>>>,
+    count: u64,
+}
+
+impl KaryTree {
+    pub fn new(env: Env, fanout: u32) -> KaryTree {
+        assert_with_error!(
+            &env,
+            fanout.is_power_of_two() && (2..=16).contains(&fanout),
+            Error::InvalidFanout
+        );
+        return KaryTree {
+            fanout,
             branch: vec![&env],
             count: 0,
-        });
+        };
+    }
+
+    /// @notice Returns the number of levels of a tree with `fanout` children
+    /// per node holding as many leaves as `MerkleTree`
+    pub fn kary_depth(fanout: u32) -> u32 {
+        let _bits = fanout.trailing_zeros();
+        return (TREE_DEPTH as u32).div_ceil(_bits);
+    }
+
+    /**
+     * @notice Inserts `_node` into the tree
+     * @dev A node is hashed as soon as its last child arrives, which carries
+     * to the level above like `MerkleTree::insert` does
+     * @param _node Element to insert into tree
+     **/
+    pub fn insert(&mut self, env: Env, mut _node: BytesN<32>) {
+        assert_with_error!(&env, self.count < MAX_LEAVES, Error::MerkleTreeFull);
+
+        self.count += 1;
+        for i in 0..Self::kary_depth(self.fanout) {
+            let mut _children = self.branch.get(i).unwrap_or(vec![&env]);
+            _children.push_back(_node.clone());
+            // A full top level is kept rather than carried out of the tree
+            if _children.len() < self.fanout || i + 1 == Self::kary_depth(self.fanout) {
+                if i < self.branch.len() {
+                    self.branch.set(i, _children);
+                } else {
+                    self.branch.push_back(_children);
+                }
+                return;
+            }
+
+            _node = Self::hash_children(&env, &_children);
+            self.branch.set(i, vec![&env]);
+        }
+    }
+
+    /**
+     * @notice Calculates and returns the tree's current root
+     * @return _current Calculated root
+     **/
+    pub fn root(&self, env: Env) -> BytesN<32> {
+        let mut _zero = BytesN::from_array(&env, &[0; 32]);
+        let mut _carry: Option<BytesN<32>> = None;
+
+        for i in 0..Self::kary_depth(self.fanout) {
+            let mut _children = self.branch.get(i).unwrap_or(vec![&env]);
+            if let Some(node) = _carry {
+                if _children.len() < self.fanout {
+                    _children.push_back(node);
+                }
+            }
+            while _children.len() < self.fanout {
+                _children.push_back(_zero.clone());
+            }
+            _carry = Some(Self::hash_children(&env, &_children));
+
+            let mut _zeroes = vec![&env];
+            while _zeroes.len() < self.fanout {
+                _zeroes.push_back(_zero.clone());
+            }
+            _zero = Self::hash_children(&env, &_zeroes);
+        }
+        return _carry.unwrap_or(_zero);
+    }
+
+    /**
+     * @notice Calculates the root for the given leaf `_item`, its proof and its
+     * index
+     * @dev Reverts with `InvalidRange` if `_index` is not below `MAX_LEAVES`,
+     * as the top levels can hold more leaves than that and would otherwise
+     * wrap it around to a lower index
+     * @param _fanout Children per node
+     * @param _item Merkle leaf
+     * @param _branch The `_fanout - 1` siblings of every level, leaf level first
+     * @param _index Index of `_item` in tree
+     * @return Calculated merkle root
+     **/
+    pub fn branch_root(
+        env: Env,
+        _fanout: u32,
+        _item: BytesN<32>,
+        _branch: Vec<Vec<BytesN<32>>>,
+        _index: u64,
+    ) -> BytesN<32> {
+        assert_with_error!(&env, _index < MAX_LEAVES, Error::InvalidRange);
+        assert_with_error!(
+            &env,
+            _branch.len() == Self::kary_depth(_fanout)
+                && _branch.iter().all(|level| level.len() == _fanout - 1),
+            Error::MerkleTreeInvalidVecSize
+        );
+
+        let mut _current = _item;
+        let mut _index = _index;
+        for _siblings in _branch.iter() {
+            let _position = (_index % _fanout as u64) as u32;
+            let mut _children = _siblings;
+            _children.insert(_position, _current);
+            _current = Self::hash_children(&env, &_children);
+            _index /= _fanout as u64;
+        }
+        return _current;
+    }
+
+    fn hash_children(env: &Env, children: &Vec<BytesN<32>>) -> BytesN<32> {
+        let mut _vec = vec![env];
+        for child in children.iter() {
+            _vec.push_back(child.to_array());
+        }
+        return BytesN::from_array(env, &MerkleTree::keccak256(_vec));
+    }
+}
+
+#[cfg(feature = "helper-contract")]
+const TREE: Symbol = symbol_short!("TREE");
+#[cfg(feature = "helper-contract")]
+const HASH_OPS: Symbol = symbol_short!("HASH_OPS");
+#[cfg(feature = "helper-contract")]
+const FIRST_PATH: Symbol = symbol_short!("FIRSTPATH");
+#[cfg(feature = "helper-contract")]
+const CONFIG: Symbol = symbol_short!("CONFIG");
+#[cfg(feature = "helper-contract")]
+const LEAF: Symbol = symbol_short!("LEAF");
+#[cfg(feature = "helper-contract")]
+const INSERTER: Symbol = symbol_short!("INSERTER");
+#[cfg(feature = "helper-contract")]
+const INSERT: Symbol = symbol_short!("insert");
+#[cfg(feature = "helper-contract")]
+const INSERT_BATCH: Symbol = symbol_short!("ins_batch");
+#[cfg(feature = "helper-contract")]
+const VERSION: Symbol = symbol_short!("VERSION");
+#[cfg(feature = "helper-contract")]
+const VERSION_ROOT: Symbol = symbol_short!("VROOT");
+#[cfg(feature = "helper-contract")]
+const NULLIFIER: Symbol = symbol_short!("NULLIFIER");
+#[cfg(feature = "helper-contract")]
+const SPENT: Symbol = symbol_short!("SPENT");
+#[cfg(feature = "helper-contract")]
+const KARY_TREE: Symbol = symbol_short!("KTREE");
+#[cfg(feature = "helper-contract")]
+const MMR: Symbol = symbol_short!("MMR");
+#[cfg(feature = "helper-contract")]
+const MMR_NODE: Symbol = symbol_short!("MMR_NODE");
+#[cfg(feature = "helper-contract")]
+const ROOT_TAG: Symbol = symbol_short!("ROOT_TAG");
+#[cfg(feature = "helper-contract")]
+const ROOT_TAGS: Symbol = symbol_short!("ROOT_TAGS");
+#[cfg(feature = "helper-contract")]
+const ROOT_HISTORY: Symbol = symbol_short!("ROOTS");
+#[cfg(feature = "helper-contract")]
+const FROZEN: Symbol = symbol_short!("FROZEN");
+
+/// Ledgers the persistent tree entry is kept alive for on every access
+/// without a `ttl_extension`, about 30 days of 5 second ledgers.
+#[cfg(feature = "helper-contract")]
+const TREE_TTL: u32 = 518_400;
+
+/// Number of past roots kept by the helper contract, see `is_known_root`.
+#[cfg(feature = "helper-contract")]
+const ROOT_HISTORY_SIZE: u64 = 32;
+
+/// Index of a leaf in the tree. A distinct type from the `u64` leaf count,
+/// so one can't be passed where the other is expected, only built for a
+/// leaf the tree holds. An index decoded from a contract argument skips that
+/// check, so the helper contract checks it against its count again.
+#[contracttype]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct LeafIndex(u64);
+
+impl LeafIndex {
+    /// Index `index` of a tree holding `count` leaves, if it holds that leaf.
+    pub fn new(index: u64, count: u64) -> Option<LeafIndex> {
+        if index >= count {
+            return None;
+        }
+        return Some(LeafIndex(index));
+    }
+
+    /// Index of the last leaf of a tree holding `count` leaves, if any.
+    pub fn last(count: u64) -> Option<LeafIndex> {
+        return count.checked_sub(1).map(LeafIndex);
+    }
+}
+
+impl From<LeafIndex> for u64 {
+    fn from(index: LeafIndex) -> Self {
+        return index.0;
+    }
+}
+
+/// A leaf with its merkle proof, as consumed by `MerkleTree::branch_root`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MerkleProof {
+    pub leaf: BytesN<32>,
+    pub branch: Vec<BytesN<32>>,
+    pub index: LeafIndex,
+}
+
+/// A proof with the root it was generated against, so it can be checked
+/// away from the contract state, see `Contract::portable_proof`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PortableProof {
+    pub leaf: BytesN<32>,
+    pub branch: Vec<BytesN<32>>,
+    pub index: LeafIndex,
+    pub root: BytesN<32>,
+}
+
+/// Everything a caller needs right after an insert, see
+/// `Contract::insert_full`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InsertResult {
+    /// Index the leaf was inserted at.
+    pub index: u32,
+    /// Leaf count after the insert.
+    pub count: u64,
+    /// Root after the insert.
+    pub root: BytesN<32>,
+}
+
+/// Data of the `insert` event published on every insert. `root` must equal
+/// the root of `previous_root`'s tree with `leaf` appended at `index`, so an
+/// indexer can check the chain of roots is unbroken.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InsertEvent {
+    pub index: u32,
+    pub leaf: BytesN<32>,
+    pub previous_root: BytesN<32>,
+    pub root: BytesN<32>,
+}
+
+/// Data of the `ins_batch` event published once per batch insert instead of
+/// an `insert` event per leaf. `root` must equal the root of
+/// `previous_root`'s tree with `leaves` appended from index `start`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchInsertEvent {
+    pub start: u64,
+    pub leaves: Vec<BytesN<32>>,
+    pub previous_root: BytesN<32>,
+    pub root: BytesN<32>,
+}
+
+/// Everything an off-chain verifier needs to check proofs against the
+/// current root, see `Contract::verification_bundle`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerificationBundle {
+    pub root: BytesN<32>,
+    pub count: u64,
+    pub depth: u32,
+    pub hash_algo: HashAlgo,
+    /// Zero hash of every level, the empty leaf first.
+    pub zero_hashes: Vec<BytesN<32>>,
+    /// Leaves are bound to their index, see `TreeConfig::bind_index`.
+    pub bind_index: bool,
+}
+
+/// How the contract turns an inserted value into the leaf it commits, see
+/// `Contract::leaf_rule`. The steps apply in order:
+/// 1. `insert_salted` hashes `keccak256(salt || value)`, other inserts keep
+///    the value as is.
+/// 2. With `bind_index`, the node becomes `keccak256(index || node)`, `index`
+///    being a 32-byte big-endian word.
+/// 3. The tree stores `keccak256(prefix || node)`, or the node itself for an
+///    empty `prefix`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LeafRule {
+    pub prefix: Bytes,
+    pub salt: Option<BytesN<32>>,
+    pub bind_index: bool,
+}
+
+/// Outcome of `Contract::verify_diagnostic`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerifyResult {
+    pub valid: bool,
+    /// Root computed from the supplied leaf and branch.
+    pub computed_root: BytesN<32>,
+    /// Current root of the tree.
+    pub expected_root: BytesN<32>,
+    /// First level where the supplied path differs from the tree, level 0
+    /// being the leaf. Only reported when the tree stores its leaves.
+    pub divergence_level: Option<u32>,
+}
+
+/// Outcome of `Contract::audit_against`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditResult {
+    pub matches: bool,
+    /// Root of a tree holding the supplied leaves.
+    pub computed_root: BytesN<32>,
+    /// Current root of the tree.
+    pub expected_root: BytesN<32>,
+    /// First index where the supplied leaves differ from the stored ones,
+    /// the shorter length if one set is a prefix of the other. Only known
+    /// with `store_leaves`.
+    pub first_divergence: Option<u32>,
+}
+
+/// Configuration of the helper contract, set once by `initialize`.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TreeConfig {
+    /// Salt mixed into leaves by `insert_salted` and `verify_salted`.
+    pub salt: Option<BytesN<32>>,
+    /// Keep every inserted leaf in persistent storage. This costs one
+    /// storage entry per leaf but enables the APIs that need the leaf set.
+    pub store_leaves: bool,
+    /// Account allowed to manage the contract, e.g. the inserter allowlist.
+    pub admin: Option<Address>,
+    /// Only allowlisted inserters may insert, through `insert_from`.
+    pub permissioned: bool,
+    /// Hash function of the tree.
+    pub hash_algo: HashAlgo,
+    /// The admin may overwrite stored leaves with `update_leaf`, so the
+    /// tree is no longer append-only. Requires `store_leaves` and `admin`.
+    pub mutable: bool,
+    /// Children per node of the `KaryTree` kept next to the binary tree, a
+    /// power of two up to 16. The default 2 keeps no extra tree.
+    pub fanout: u32,
+    /// Leaf inserted by `initialize`, so index 0 is always this commitment.
+    /// The tree then never has the empty root, and the first leaf a caller
+    /// inserts gets index 1.
+    pub genesis: Option<BytesN<32>>,
+    /// Value of an empty leaf, all zeros if unset. The whole zero hash
+    /// tower is built up from it, so it changes every root of the binary
+    /// tree, the empty one included.
+    pub empty_leaf: Option<BytesN<32>>,
+    /// Commit every leaf as `keccak256([index, node])`, `index` being a
+    /// 32-byte big-endian word, so its proof only holds at the index it was
+    /// inserted at. `verify` binds the leaf it is given the same way.
+    pub bind_index: bool,
+    /// Byte order of `count` in `state_commitment`, big-endian by default as
+    /// for every other word this contract hashes.
+    pub count_endianness: Endianness,
+    /// Ledgers to keep the contract state alive for, counted from every
+    /// write or read of each entry: the tree, the stored leaves, the root
+    /// history, the first leaf path, the hash count and the nullifiers.
+    /// Unset, only the tree entry is extended, by `TREE_TTL` ledgers.
+    pub ttl_extension: Option<u32>,
+    /// Levels of the binary tree, from 1 to `TREE_DEPTH`. It holds
+    /// `2^depth` leaves and its proofs `depth` siblings, and the namespace
+    /// trees and the trees rebuilt from a frontier share it. Only the k-ary
+    /// tree and the MMR have their own shape.
+    pub depth: u32,
+}
+
+#[cfg(feature = "helper-contract")]
+impl Default for TreeConfig {
+    fn default() -> Self {
+        TreeConfig {
+            salt: None,
+            store_leaves: false,
+            admin: None,
+            permissioned: false,
+            hash_algo: HashAlgo::Keccak256,
+            mutable: false,
+            fanout: 2,
+            genesis: None,
+            empty_leaf: None,
+            bind_index: false,
+            count_endianness: Endianness::Big,
+            ttl_extension: None,
+            depth: TREE_DEPTH as u32,
+        }
+    }
+}
+
+/// Tree as the first version of the contract kept it in instance storage,
+/// before the count was widened and the depth and hash became configurable.
+#[cfg(feature = "helper-contract")]
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct LegacyMerkleTree {
+    branch: Vec<BytesN<32>>,
+    count: u32,
+}
+
+#[cfg(feature = "helper-contract")]
+impl From<LegacyMerkleTree> for MerkleTree {
+    fn from(legacy: LegacyMerkleTree) -> Self {
+        MerkleTree {
+            branch: legacy.branch,
+            count: legacy.count as u64,
+            depth: TREE_DEPTH as u32,
+            hash_algo: HashAlgo::Keccak256,
+        }
+    }
+}
+
+/**
+ * This a basic helper contract used to assist with tests.
+ */
+#[cfg(feature = "helper-contract")]
+#[contract]
+pub struct Contract;
+
+#[cfg(feature = "helper-contract")]
+#[contractimpl]
+impl Contract {
+    /// Stores the contract configuration. Verifiers need the same salt to
+    /// rebuild salted leaves, so it is not secret.
+    pub fn initialize(env: Env, config: TreeConfig) {
+        assert_with_error!(
+            &env,
+            !env.storage().instance().has(&CONFIG),
+            Error::AlreadyInitialized
+        );
+        assert_with_error!(
+            &env,
+            !config.permissioned || config.admin.is_some(),
+            Error::AdminNotSet
+        );
+        assert_with_error!(
+            &env,
+            config.fanout == 2 || config.hash_algo == HashAlgo::Keccak256,
+            Error::UnsupportedHashAlgo
+        );
+        assert_with_error!(
+            &env,
+            (1..=MAX_SUPPORTED_DEPTH).contains(&config.depth),
+            Error::InvalidDepth
+        );
+        assert_with_error!(
+            &env,
+            !config.mutable || config.store_leaves,
+            Error::LeavesNotStored
+        );
+        if config.fanout != 2 {
+            let tree = KaryTree::new(env.clone(), config.fanout);
+            env.storage().instance().set(&KARY_TREE, &tree);
+        }
+        assert_with_error!(
+            &env,
+            !config.mutable || config.admin.is_some(),
+            Error::AdminNotSet
+        );
+        env.storage().instance().set(&CONFIG, &config);
+
+        if let Some(genesis) = config.genesis {
+            Self::append(env, genesis);
+        }
+    }
+
+    /// Returns the deepest tree `TreeConfig::depth` accepts.
+    pub fn max_supported_depth(_env: Env) -> u32 {
+        return MAX_SUPPORTED_DEPTH;
+    }
+
+    /// Returns the configuration set by `initialize`, or the default one if
+    /// the contract was never initialized.
+    pub fn get_config(env: Env) -> TreeConfig {
+        return env.storage().instance().get(&CONFIG).unwrap_or_default();
+    }
+
+    /// Returns a hash of everything in the configuration that changes the
+    /// roots or the accepted proofs, for clients to compare with the one
+    /// they expect. The admin is left out. The encoding is `keccak256` of the
+    /// 32-byte words `[depth, hash_algo, fanout, flags, salt, genesis,
+    /// empty_leaf]`, a missing value being zero and flagged in `flags`.
+    pub fn config_fingerprint(env: Env) -> BytesN<32> {
+        let config = Self::get_config(env.clone());
+        let zero = BytesN::from_array(&env, &[0; 32]);
+        let flags = (config.store_leaves as u64)
+            | (config.permissioned as u64) << 1
+            | (config.mutable as u64) << 2
+            | (config.salt.is_some() as u64) << 3
+            | (config.genesis.is_some() as u64) << 4
+            | (config.empty_leaf.is_some() as u64) << 5
+            | (config.bind_index as u64) << 6
+            | ((config.count_endianness == Endianness::Little) as u64) << 7;
+
+        let _vec = vec![
+            &env,
+            MerkleTree::to_word(config.depth as u64),
+            MerkleTree::to_word(config.hash_algo as u64),
+            MerkleTree::to_word(config.fanout as u64),
+            MerkleTree::to_word(flags),
+            config.salt.unwrap_or(zero.clone()).to_array(),
+            config.genesis.unwrap_or(zero.clone()).to_array(),
+            config.empty_leaf.unwrap_or(zero).to_array(),
+        ];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    /// Returns the stored tree, or an empty one if nothing was inserted yet.
+    /// The tree lives in persistent storage. A `LegacyMerkleTree` left in
+    /// instance storage by the first version is still read as a Keccak tree
+    /// of depth `TREE_DEPTH`, and moves to persistent storage on the next
+    /// write. Fails with `StateCorrupted` if the stored value has neither
+    /// shape, e.g. when it was written by an incompatible contract version, and with
+    /// `FrontierInconsistent` if its frontier is too short for its count,
+    /// which `root` would otherwise silently fill with zeros.
+    pub fn get_tree(env: Env) -> MerkleTree {
+        Self::extend_ttl(env.clone());
+        let stored: Option<Val> = env.storage().persistent().get(&TREE);
+        let tree = match stored {
+            Some(val) => MerkleTree::try_from_val(&env, &val)
+                .unwrap_or_else(|_| panic_with_error!(&env, Error::StateCorrupted)),
+            None => match env.storage().instance().get::<_, Val>(&TREE) {
+                Some(val) => LegacyMerkleTree::try_from_val(&env, &val)
+                    .unwrap_or_else(|_| panic_with_error!(&env, Error::StateCorrupted))
+                    .into(),
+                None => Self::empty_tree(env.clone()),
+            },
+        };
+
+        // Every level set in `count` needs its frontier node.
+        let levels = 64 - tree.count.leading_zeros();
+        assert_with_error!(
+            &env,
+            tree.branch.len() >= levels,
+            Error::FrontierInconsistent
+        );
+        return tree;
+    }
+
+    /// Inserts `node` into the separate tree of `namespace`, e.g. during a
+    /// migration between two trees, and returns its index. Namespaced trees
+    /// only keep their frontier, none of the main tree's extras.
+    pub fn insert_into(env: Env, namespace: Symbol, node: BytesN<32>) -> u32 {
+        Self::require_open(env.clone());
+        let mut tree = Self::namespace_tree(env.clone(), namespace.clone());
+        let index = tree.insert(env.clone(), node);
+        let key = (TREE, namespace);
+        env.storage().persistent().set(&key, &tree);
+        env.storage().instance().remove(&key);
+        return index;
+    }
+
+    /// Returns the root of the tree of `namespace`, see `insert_into`.
+    pub fn get_namespace_root(env: Env, namespace: Symbol) -> BytesN<32> {
+        let tree = Self::namespace_tree(env.clone(), namespace);
+        return Self::tree_root(env, &tree);
+    }
+
+    /// Returns the tree of `namespace`, empty if nothing was inserted into it
+    /// yet, like `get_tree` for the main tree.
+    pub fn get_namespace_tree(env: Env, namespace: Symbol) -> MerkleTree {
+        return Self::namespace_tree(env, namespace);
+    }
+
+    /// Returns whether `item` is in the tree of `namespace` at `index`.
+    pub fn verify_in_namespace(
+        env: Env,
+        namespace: Symbol,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let tree = Self::namespace_tree(env.clone(), namespace);
+        let root = MerkleTree::branch_root_with(
+            env.clone(),
+            tree.hash_algo(),
+            tree.depth(),
+            item,
+            branch,
+            index,
+        );
+        return root == Self::tree_root(env, &tree);
+    }
+
+    /// Returns the number of leaves inserted so far, which is also the index
+    /// the next leaf will get. This is not the capacity of the tree.
+    pub fn get_count(env: Env) -> u64 {
+        return Self::get_tree(env).count();
+    }
+
+    /// Returns the frontier of the tree, see `MerkleTree::branch`. With
+    /// `get_count` it is the whole insertion state: a mirror holding both
+    /// inserts the same leaves into the same roots, e.g. through
+    /// `verify_with_frontier`, without replaying past inserts.
+    pub fn get_branch(env: Env) -> Vec<BytesN<32>> {
+        return Self::get_tree(env).branch();
+    }
+
+    /// Returns the depth of the tree, the number of siblings in its proofs.
+    pub fn get_depth(env: Env) -> u32 {
+        return Self::get_tree(env).depth();
+    }
+
+    /// Returns the number of ledgers the contract state and the tree are
+    /// guaranteed to live for, which this read extends like any other to the
+    /// `ttl_extension` policy. 0 without a policy.
+    pub fn ttl_remaining(env: Env) -> u32 {
+        Self::get_tree(env.clone());
+        return Self::get_config(env).ttl_extension.unwrap_or(0);
+    }
+
+    /// Inserts `node`. Fails with `Unauthorized` on a permissioned tree,
+    /// where inserts go through `insert_from`.
+    pub fn insert(env: Env, node: BytesN<32>) -> MerkleTree {
+        Self::require_open(env.clone());
+        return Self::append(env, node);
+    }
+
+    /// Inserts `node` like `insert` but returns only the index it landed at,
+    /// which is the `index` its proof must be checked with.
+    pub fn insert_and_get_index(env: Env, node: BytesN<32>) -> u32 {
+        Self::require_open(env.clone());
+        let (_, index) = Self::append_indexed(env, node);
+        return index;
+    }
+
+    /// Inserts `node` like `insert` and returns its index with the new count
+    /// and root, read from the same state so no later call can race them.
+    pub fn insert_full(env: Env, node: BytesN<32>) -> InsertResult {
+        let tree = Self::insert(env.clone(), node);
+        return InsertResult {
+            index: (tree.count - 1) as u32,
+            count: tree.count,
+            root: Self::tree_root(env, &tree),
+        };
+    }
+
+    /// Inserts `node` given as an untyped value, failing with
+    /// `MerkleTreeInvalidVecSize` instead of a host conversion error when it
+    /// is not exactly 32 bytes.
+    pub fn insert_val(env: Env, node: Val) -> MerkleTree {
+        let bytes = Bytes::try_from_val(&env, &node)
+            .unwrap_or_else(|_| panic_with_error!(&env, Error::MerkleTreeInvalidVecSize));
+        let node: BytesN<32> = bytes
+            .try_into()
+            .unwrap_or_else(|_| panic_with_error!(&env, Error::MerkleTreeInvalidVecSize));
+        return Self::insert(env, node);
+    }
+
+    /// Inserts `node` on behalf of `inserter`, which must authorize the call
+    /// and, on a permissioned tree, be allowlisted with `add_inserter`.
+    pub fn insert_from(env: Env, inserter: Address, node: BytesN<32>) -> MerkleTree {
+        Self::require_inserter(env.clone(), inserter);
+        return Self::append(env, node);
+    }
+
+    /// Inserts a commitment to `node` bound to `sender`, who must authorize
+    /// the call. The leaf is `keccak256([keccak256(sender), node])`, where
+    /// `sender` is hashed in its XDR encoding, see `verify_bound`.
+    pub fn insert_bound(env: Env, sender: Address, node: BytesN<32>) -> MerkleTree {
+        Self::require_inserter(env.clone(), sender.clone());
+        let leaf = Self::bound_leaf(env.clone(), sender, node);
+        return Self::append(env, leaf);
+    }
+
+    /// Inserts `keccak256([salt, value])`, so the same public value gives
+    /// unrelated leaves in trees with different salts.
+    pub fn insert_salted(env: Env, value: BytesN<32>) -> MerkleTree {
+        Self::require_open(env.clone());
+        let leaf = Self::salted_leaf(env.clone(), value);
+        return Self::append(env, leaf);
+    }
+
+    /// Inserts the keccak256 hash of every preimage, in order, and returns
+    /// the indices they were inserted at. Fails with `MerkleTreeFull` before
+    /// inserting anything if the tree cannot hold them all.
+    pub fn insert_hashed(env: Env, preimages: Vec<Bytes>) -> Vec<u32> {
+        Self::require_open(env.clone());
+        let mut nodes = vec![&env];
+        for preimage in preimages {
+            nodes.push_back(BytesN::from_array(
+                &env,
+                &MerkleTree::keccak256_bytes(&preimage),
+            ));
+        }
+
+        // Every inserted leaf is below the capacity, so its index fits a u32
+        let (start, inserted) = Self::append_batch(env.clone(), nodes);
+        let mut indices = vec![&env];
+        for i in 0..inserted {
+            indices.push_back((start + i as u64) as u32);
+        }
+        return indices;
+    }
+
+    /// Inserts every node in order, like `insert` does one at a time, and
+    /// returns the index of the first one with the number inserted. Fails
+    /// with `MerkleTreeFull` before inserting anything if the tree cannot
+    /// hold them all.
+    pub fn insert_batch(env: Env, nodes: Vec<BytesN<32>>) -> (u64, u32) {
+        Self::require_open(env.clone());
+        return Self::append_batch(env, nodes);
+    }
+
+    /// Allows `inserter` to insert into a permissioned tree. Admin only.
+    pub fn add_inserter(env: Env, inserter: Address) {
+        Self::require_admin(env.clone());
+        env.storage().persistent().set(&(INSERTER, inserter), &true);
+    }
+
+    /// Revokes an inserter added with `add_inserter`. Admin only.
+    pub fn remove_inserter(env: Env, inserter: Address) {
+        Self::require_admin(env.clone());
+        env.storage().persistent().remove(&(INSERTER, inserter));
+    }
+
+    /// Starts a fresh tree, e.g. to rotate trees per epoch, keeping the
+    /// configuration. The genesis leaf is appended again like `initialize`
+    /// does, and everything derived from the previous tree is forgotten: its
+    /// roots, versions, tags, spent nullifiers, hash count and the Merkle
+    /// Mountain Range. Stored leaves are overwritten by the next inserts.
+    /// Admin only.
+    pub fn reset(env: Env) {
+        Self::require_admin(env.clone());
+        let config = Self::get_config(env.clone());
+
+        Self::save_tree(env.clone(), &Self::empty_tree(env.clone()));
+        if config.fanout != 2 {
+            let tree = KaryTree::new(env.clone(), config.fanout);
+            env.storage().instance().set(&KARY_TREE, &tree);
+        }
+        env.storage().persistent().remove(&FIRST_PATH);
+        env.storage().persistent().remove(&FROZEN);
+        env.storage().persistent().remove(&HASH_OPS);
+        for slot in 0..ROOT_HISTORY_SIZE as u32 {
+            env.storage().persistent().remove(&(ROOT_HISTORY, slot));
+        }
+
+        let version: u32 = env.storage().persistent().get(&VERSION).unwrap_or(0);
+        for v in 1..=version {
+            env.storage().persistent().remove(&(VERSION_ROOT, v));
+        }
+        env.storage().persistent().remove(&VERSION);
+
+        let tags: Vec<(Address, BytesN<32>)> = env
+            .storage()
+            .persistent()
+            .get(&ROOT_TAGS)
+            .unwrap_or(vec![&env]);
+        for (owner, tag) in tags.iter() {
+            env.storage().persistent().remove(&(ROOT_TAG, owner, tag));
+        }
+        env.storage().persistent().remove(&ROOT_TAGS);
+
+        let spent: Vec<BytesN<32>> = env.storage().persistent().get(&SPENT).unwrap_or(vec![&env]);
+        for nullifier in spent.iter() {
+            env.storage().persistent().remove(&(NULLIFIER, nullifier));
+        }
+        env.storage().persistent().remove(&SPENT);
+
+        // Every node `mmr_append` stored, `count >> height` per height
+        let mmr = Self::get_mmr(env.clone());
+        for height in 0..64 - mmr.count().leading_zeros() {
+            for index in 0..mmr.count() >> height {
+                env.storage()
+                    .persistent()
+                    .remove(&(MMR_NODE, height, index));
+            }
+        }
+        env.storage().instance().remove(&MMR);
+
+        if let Some(genesis) = config.genesis {
+            Self::append(env, genesis);
+        }
+    }
+
+    /// Overwrites the leaf at `index` on a mutable tree and returns the new
+    /// version, starting at 1. The root after every update is kept for
+    /// `verify_at_version`. Admin only.
+    pub fn update_leaf(env: Env, index: u32, leaf: BytesN<32>) -> u32 {
+        Self::require_admin(env.clone());
+        assert_with_error!(
+            &env,
+            Self::get_config(env.clone()).mutable,
+            Error::TreeNotMutable
+        );
+        let count = Self::get_tree(env.clone()).count;
+        assert_with_error!(&env, (index as u64) < count, Error::InvalidRange);
+
+        let leaf = Self::committed_leaf(env.clone(), index as u64, leaf);
+        env.storage().persistent().set(&(LEAF, index), &leaf);
+
+        // The frontier and the cached first path may both cover the old
+        // leaf, so replay every stored leaf into a fresh tree.
+        env.storage().persistent().remove(&FIRST_PATH);
+        let fanout = Self::get_config(env.clone()).fanout;
+        let mut kary = KaryTree::new(env.clone(), fanout);
+        let mut tree = Self::empty_tree(env.clone());
+        for i in 0..count {
+            let node = Self::stored_leaf(env.clone(), i);
+            if tree.count > 0 && (tree.count + 1).is_power_of_two() {
+                Self::record_first_path(env.clone(), &tree, node.clone());
+            }
+            tree.insert(env.clone(), node.clone());
+            if fanout != 2 {
+                kary.insert(env.clone(), node);
+            }
+        }
+        Self::save_tree(env.clone(), &tree);
+        if fanout != 2 {
+            env.storage().instance().set(&KARY_TREE, &kary);
+        }
+
+        let version: u32 = env.storage().persistent().get(&VERSION).unwrap_or(0) + 1;
+        env.storage().persistent().set(&VERSION, &version);
+        let checkpoint = (tree.count, Self::tree_root(env.clone(), &tree));
+        env.storage()
+            .persistent()
+            .set(&(VERSION_ROOT, version), &checkpoint);
+        Self::record_root(env.clone(), &tree);
+        return version;
+    }
+
+    /// Appends `leaf` to the tree described by `frontier` and `count`,
+    /// without touching the stored one, and returns the new frontier, count
+    /// and root. Fails with `FrontierInconsistent` if the frontier is too
+    /// short for `count`.
+    pub fn step_root(
+        env: Env,
+        frontier: Vec<BytesN<32>>,
+        count: u64,
+        leaf: BytesN<32>,
+    ) -> (Vec<BytesN<32>>, u64, BytesN<32>) {
+        let levels = 64 - count.leading_zeros();
+        assert_with_error!(&env, frontier.len() >= levels, Error::FrontierInconsistent);
+
+        let mut tree = Self::tree_from_frontier(env.clone(), frontier, count);
+        tree.insert(env.clone(), leaf);
+        let root = Self::tree_root(env, &tree);
+        return (tree.branch, tree.count, root);
+    }
+
+    /// Returns whether appending `appended` to the tree described by
+    /// `old_frontier` and `old_count` gives `new_root`. The old root follows
+    /// from the same state, so this proves the tree went from one root to the
+    /// other by those appends. A frontier that can't belong to `old_count`
+    /// never verifies.
+    pub fn verify_extension(
+        env: Env,
+        old_frontier: Vec<BytesN<32>>,
+        old_count: u64,
+        appended: Vec<BytesN<32>>,
+        new_root: BytesN<32>,
+    ) -> bool {
+        let levels = 64 - old_count.leading_zeros();
+        let mut tree = Self::tree_from_frontier(env.clone(), old_frontier, old_count);
+        if tree.branch.len() < levels
+            || tree.branch.len() > tree.depth + 1
+            || old_count + appended.len() as u64 > tree.capacity()
+        {
+            return false;
+        }
+
+        for node in appended {
+            tree.insert(env.clone(), node);
+        }
+        return Self::tree_root(env, &tree) == new_root;
+    }
+
+    /// Pins the current root for `verify_and_nullify`, so claims keep
+    /// verifying while the tree grows. Calling it again moves the pin to the
+    /// new current root. Admin only.
+    pub fn freeze_root(env: Env) {
+        Self::require_admin(env.clone());
+        let tree = Self::get_tree(env.clone());
+        let frozen = (tree.count, Self::tree_root(env.clone(), &tree));
+        env.storage().persistent().set(&FROZEN, &frozen);
+    }
+
+    /// Returns the nullifier of the leaf `item` at `index`, the only one
+    /// `verify_and_nullify` accepts for it. The encoding is
+    /// `keccak256([index, item])` with `index` as a 32-byte big-endian word.
+    pub fn nullifier(env: Env, item: BytesN<32>, index: u64) -> BytesN<32> {
+        let _vec = vec![&env, MerkleTree::to_word(index), item.to_array()];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    /// Verifies a leaf against the root pinned by `freeze_root` and spends
+    /// its nullifier, see `nullifier`, so the same leaf can't be claimed
+    /// twice. Fails with `RootNotFrozen`, `InvalidProof`, `InvalidNullifier`
+    /// or `NullifierUsed`, in which case nothing is recorded.
+    pub fn verify_and_nullify(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        nullifier: BytesN<32>,
+    ) {
+        let (count, root): (u64, BytesN<32>) = env
+            .storage()
+            .persistent()
+            .get(&FROZEN)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::RootNotFrozen));
+        assert_with_error!(
+            &env,
+            Self::verify_leaf_at(env.clone(), item.clone(), branch, index, count, root),
+            Error::InvalidProof
+        );
+        assert_with_error!(
+            &env,
+            nullifier == Self::nullifier(env.clone(), item, u64::from(index)),
+            Error::InvalidNullifier
+        );
+        let key = (NULLIFIER, nullifier);
+        assert_with_error!(
+            &env,
+            !env.storage().persistent().has(&key),
+            Error::NullifierUsed
+        );
+        env.storage().persistent().set(&key, &true);
+        Self::extend_entry_ttl(env.clone(), &key);
+
+        // Kept so `reset` can find every spent nullifier
+        let mut spent: Vec<BytesN<32>> =
+            env.storage().persistent().get(&SPENT).unwrap_or(vec![&env]);
+        spent.push_back(key.1);
+        env.storage().persistent().set(&SPENT, &spent);
+        Self::extend_entry_ttl(env, &SPENT);
+    }
+
+    /// Returns whether `nullifier` was spent by `verify_and_nullify`.
+    pub fn is_nullified(env: Env, nullifier: BytesN<32>) -> bool {
+        let key = (NULLIFIER, nullifier);
+        Self::extend_entry_ttl(env.clone(), &key);
+        return env.storage().persistent().has(&key);
+    }
+
+    /// Pins the current root under `tag`, e.g. a hash of "epoch-5". Tags are
+    /// namespaced by `owner`, who must authorize the commit, so nobody can
+    /// squat another caller's tag, and a tag can't be moved once committed.
+    pub fn commit_root_reference(env: Env, owner: Address, tag: BytesN<32>) {
+        owner.require_auth();
+        let key = (ROOT_TAG, owner, tag);
+        assert_with_error!(
+            &env,
+            !env.storage().persistent().has(&key),
+            Error::TagAlreadyCommitted
+        );
+        let tree = Self::get_tree(env.clone());
+        let checkpoint = (tree.count, Self::tree_root(env.clone(), &tree));
+        env.storage().persistent().set(&key, &checkpoint);
+
+        // Kept so `reset` can find every committed tag
+        let mut tags: Vec<(Address, BytesN<32>)> = env
+            .storage()
+            .persistent()
+            .get(&ROOT_TAGS)
+            .unwrap_or(vec![&env]);
+        tags.push_back((key.1, key.2));
+        env.storage().persistent().set(&ROOT_TAGS, &tags);
+    }
+
+    /// Verifies a leaf against the root `owner` committed under `tag`.
+    /// Unknown tags never verify.
+    pub fn verify_against_tag(
+        env: Env,
+        owner: Address,
+        tag: BytesN<32>,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let checkpoint: Option<(u64, BytesN<32>)> =
+            env.storage().persistent().get(&(ROOT_TAG, owner, tag));
+        return match checkpoint {
+            Some((count, root)) => Self::verify_leaf_at(env, item, branch, index, count, root),
+            None => false,
+        };
+    }
+
+    /// Verifies a leaf against the roots of both namespaces, succeeding if
+    /// either accepts it, for leaves split across an old and a new tree.
+    pub fn verify_in_either(
+        env: Env,
+        namespaces: (Symbol, Symbol),
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        return Self::verify_in_namespace(
+            env.clone(),
+            namespaces.0,
+            item.clone(),
+            branch.clone(),
+            index,
+        ) || Self::verify_in_namespace(env, namespaces.1, item, branch, index);
+    }
+
+    /// Returns the root the tree would have if `old_item` at `index` were
+    /// replaced by `new_item`, or `None` if the proof of `old_item` doesn't
+    /// verify against the current root. Nothing is stored.
+    pub fn root_after_replace(
+        env: Env,
+        old_item: BytesN<32>,
+        new_item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: u64,
+    ) -> Option<BytesN<32>> {
+        let count = Self::get_tree(env.clone()).count;
+        let index = LeafIndex::new(index, count)?;
+        if !Self::verify_leaf(env.clone(), old_item, branch.clone(), index) {
+            return None;
+        }
+        return Some(Self::leaf_root(env, new_item, branch, index));
+    }
+
+    /// Verifies a leaf against the root as of `version`, see `update_leaf`.
+    /// Unknown versions never verify.
+    pub fn verify_at_version(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        version: u32,
+    ) -> bool {
+        let checkpoint: Option<(u64, BytesN<32>)> =
+            env.storage().persistent().get(&(VERSION_ROOT, version));
+        return match checkpoint {
+            Some((count, root)) => Self::verify_leaf_at(env, item, branch, index, count, root),
+            None => false,
+        };
+    }
+
+    /// Compacts the stored frontier, see `MerkleTree::compact`. Admin only.
+    pub fn compact(env: Env) -> MerkleTree {
+        Self::require_admin(env.clone());
+        let mut tree = Self::get_tree(env.clone());
+        tree.compact(env.clone());
+        Self::save_tree(env.clone(), &tree);
+        return tree;
+    }
+
+    /// Returns whether `inserter` is allowlisted.
+    pub fn is_inserter(env: Env, inserter: Address) -> bool {
+        return env.storage().persistent().has(&(INSERTER, inserter));
+    }
+
+    /// Returns the root of the `KaryTree` holding the same leaves as the
+    /// binary tree. Fails with `InvalidFanout` if `fanout` is 2.
+    pub fn get_kary_root(env: Env) -> BytesN<32> {
+        return Self::kary_tree(env.clone()).root(env);
+    }
+
+    /// Verifies a leaf against `get_kary_root`, `branch` holding `fanout - 1`
+    /// siblings per level.
+    pub fn verify_kary(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<Vec<BytesN<32>>>,
+        index: LeafIndex,
+    ) -> bool {
+        let tree = Self::kary_tree(env.clone());
+        let index = u64::from(index);
+        let root = KaryTree::branch_root(env.clone(), tree.fanout, item, branch, index);
+        return root == tree.root(env);
+    }
+
+    /// Appends `node` to the Merkle Mountain Range kept apart from the tree
+    /// and returns its index. See `Mmr`.
+    pub fn mmr_append(env: Env, node: BytesN<32>) -> u64 {
+        Self::require_open(env.clone());
+        let mut mmr = Self::get_mmr(env.clone());
+
+        let created = mmr.append(&env, node);
+        let index = mmr.count() - 1;
+        for (height, node) in created.iter().enumerate() {
+            let key = (MMR_NODE, height as u32, index >> height);
+            env.storage().persistent().set(&key, &node);
+        }
+        env.storage().instance().set(&MMR, &mmr);
+        return index;
+    }
+
+    /// Returns the root of the Merkle Mountain Range.
+    pub fn mmr_root(env: Env) -> BytesN<32> {
+        return Self::get_mmr(env.clone()).root(&env);
+    }
+
+    /// Returns the proof of the Merkle Mountain Range leaf at `index`. It
+    /// only holds until the next `mmr_append`, which may merge its peak.
+    pub fn mmr_proof(env: Env, index: u64) -> MmrProof {
+        let mmr = Self::get_mmr(env.clone());
+        let (_, height) = mmr.peak_of(&env, index);
+
+        let mut siblings = vec![&env];
+        for level in 0..height {
+            let key = (MMR_NODE, level, (index >> level) ^ 1);
+            let sibling: BytesN<32> = env
+                .storage()
+                .persistent()
+                .get(&key)
+                .unwrap_or_else(|| panic_with_error!(&env, Error::StateCorrupted));
+            siblings.push_back(sibling);
+        }
+        return MmrProof {
+            index,
+            siblings,
+            peaks: mmr.peaks(),
+        };
+    }
+
+    /// Verifies a leaf against the current Merkle Mountain Range root.
+    pub fn mmr_verify(env: Env, leaf: BytesN<32>, proof: MmrProof) -> bool {
+        return Self::get_mmr(env.clone()).verify(&env, leaf, proof);
+    }
+
+    pub fn get_root(env: Env) -> BytesN<32> {
+        let tree = Self::get_tree(env.clone());
+        let root = Self::tree_root(env.clone(), &tree);
+        return root;
+    }
+
+    /// Returns `keccak256([root, count])`, committing to the current root and
+    /// the number of leaves it holds, `count` being encoded with the
+    /// configured `count_endianness`.
+    pub fn state_commitment(env: Env) -> BytesN<32> {
+        let tree = Self::get_tree(env.clone());
+        let root = Self::tree_root(env.clone(), &tree);
+        let count = Self::count_word(env.clone(), tree.count);
+        let _vec = vec![&env, root.to_array(), count];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    /// Returns the serialized size in bytes of the stored tree, which grows
+    /// with the frontier and is what storage rent is charged on.
+    pub fn storage_footprint(env: Env) -> u32 {
+        let tree = Self::get_tree(env.clone());
+        return tree.to_xdr(&env).len();
+    }
+
+    /// Returns the last index of the complete subtree of `2^h` leaves at the
+    /// start of the tree, `h` being `floor(log2(count))`, or `None` while
+    /// `h` is 0. The lowest `h` levels of the proofs up to that index never
+    /// change again, the levels above still do until the tree is full.
+    pub fn max_finalized_index(env: Env) -> Option<u32> {
+        let tree = Self::get_tree(env);
+        if tree.count < 2 {
+            return None;
+        }
+
+        let height = 63 - tree.count.leading_zeros();
+        return Some(((1u64 << height) - 1) as u32);
+    }
+
+    /// Returns how many more inserts complete the next subtree holding the
+    /// leaf at `index`, which freezes one more level of its proof, or 0 once
+    /// the tree is full. The sibling at level `i` is final when the subtree of
+    /// `2^(i + 1)` leaves holding `index` is complete.
+    pub fn leaves_until_stable(env: Env, index: u32) -> u32 {
+        let tree = Self::get_tree(env.clone());
+        assert_with_error!(&env, (index as u64) < tree.count, Error::InvalidRange);
+        if tree.is_full() {
+            return 0;
+        }
+
+        for height in 1..=tree.depth() {
+            let end = ((index as u64 >> height) + 1) << height;
+            if end > tree.count {
+                return (end - tree.count) as u32;
+            }
+        }
+        return 0;
+    }
+
+    /// Returns the proof for the leaf at index 0, or `None` for an empty tree.
+    ///
+    /// The sibling at level `i` is final once `count` reaches `2^(i + 1)`,
+    /// so the lowest levels settle first but the proof only becomes
+    /// permanently stable when the tree is full. Completed siblings are kept
+    /// by `insert`, the rest come from the frontier and the zero hashes.
+    pub fn first_leaf_proof(env: Env) -> Option<Vec<BytesN<32>>> {
+        let tree = Self::get_tree(env.clone());
+        if tree.count == 0 {
+            return None;
+        }
+
+        let _zeroes = Self::zeroes(env.clone());
+        Self::extend_entry_ttl(env.clone(), &FIRST_PATH);
+        let mut proof: Vec<BytesN<32>> = env
+            .storage()
+            .persistent()
+            .get(&FIRST_PATH)
+            .unwrap_or(vec![&env]);
+
+        for i in proof.len()..tree.depth {
+            if tree.count > 1 << i {
+                // The right subtree at this level is partially filled.
+                let sibling = tree.subtree_root_with_ctx(env.clone(), _zeroes.clone(), i);
+                proof.push_back(sibling);
+            } else {
+                proof.push_back(_zeroes.get_unchecked(i));
+            }
+        }
+        return Some(proof);
+    }
+
+    /// Returns whether the tree is full, so an `insert` would fail with
+    /// `MerkleTreeFull`.
+    pub fn is_full(env: Env) -> bool {
+        return Self::get_tree(env).is_full();
+    }
+
+    /// Returns how many leaves can still be inserted.
+    pub fn remaining_capacity(env: Env) -> u64 {
+        return Self::get_tree(env).remaining_capacity();
+    }
+
+    /// Returns the frontier levels the next `insert` will touch, in order.
+    /// Every level but the last is read and folded into the new node, the
+    /// last one is where the new node is written. Empty if the tree is full.
+    pub fn next_insert_touches(env: Env) -> Vec<u32> {
+        let tree = Self::get_tree(env.clone());
+        let mut levels = vec![&env];
+        if tree.is_full() {
+            return levels;
+        }
+
+        let carries = (tree.count + 1).trailing_zeros();
+        for i in 0..=carries {
+            levels.push_back(i);
+        }
+        return levels;
+    }
+
+    /// Returns the proof of the leaf at `index` together with the current
+    /// root. The bundle is self-consistent, whether its root is still
+    /// acceptable is for the verifier to decide. Requires `store_leaves`.
+    pub fn portable_proof(env: Env, index: u32) -> PortableProof {
+        let tree = Self::get_tree(env.clone());
+        assert_with_error!(&env, (index as u64) < tree.count, Error::InvalidRange);
+
+        let (branch, path) = Self::stored_proof(env.clone(), index as u64);
+        return PortableProof {
+            leaf: path.get_unchecked(0),
+            branch,
+            index: LeafIndex(index as u64),
+            root: path.get_unchecked(tree.depth),
+        };
+    }
+
+    /// Returns the leaf inserted at `index`, as committed to the tree, or
+    /// `None` past the last leaf or without `store_leaves`. Stored leaves
+    /// cost one persistent entry per insert.
+    pub fn get_leaf(env: Env, index: u32) -> Option<BytesN<32>> {
+        if !Self::get_config(env.clone()).store_leaves
+            || index as u64 >= Self::get_tree(env.clone()).count
+        {
+            return None;
+        }
+        Self::extend_entry_ttl(env.clone(), &(LEAF, index));
+        return env.storage().persistent().get(&(LEAF, index));
+    }
+
+    /// Returns the `depth` siblings of the leaf at `index`, leaf level first, to
+    /// pass to `branch_root` with the same index. The frontier alone can't
+    /// give past proofs, so this requires `store_leaves`, and rebuilding
+    /// every level reads all stored leaves on each call.
+    pub fn generate_proof(env: Env, index: u32) -> Vec<BytesN<32>> {
+        let tree = Self::get_tree(env.clone());
+        assert_with_error!(&env, (index as u64) < tree.count, Error::InvalidRange);
+
+        let (branch, _) = Self::stored_proof(env, index as u64);
+        return branch;
+    }
+
+    /// Returns the root the tree had, or will have, after exactly `leaves`,
+    /// given in insertion order as committed to the tree. It uses the
+    /// configured depth and empty leaf, so the leaves of the first `k`
+    /// inserts give the root after those inserts. Does not touch the stored
+    /// tree.
+    pub fn root_from_leaves(env: Env, leaves: Vec<BytesN<32>>) -> BytesN<32> {
+        let mut tree = Self::empty_tree(env.clone());
+        tree.insert_batch(env.clone(), leaves);
+        return Self::tree_root(env, &tree);
+    }
+
+    /// Returns the Bitcoin style root of `leaves`, see
+    /// `MerkleTree::bitcoin_root`. Does not touch the stored tree.
+    pub fn bitcoin_root(env: Env, leaves: Vec<BytesN<32>>) -> BytesN<32> {
+        return MerkleTree::bitcoin_root(env, leaves);
+    }
+
+    /// Returns the root of the leaves `[start, end)` alone, padded with zero
+    /// leaves to the next power of two, see `MerkleTree::padded_root`. This
+    /// is unrelated to the tree root. Requires `store_leaves`.
+    pub fn window_root(env: Env, start: u32, end: u32) -> BytesN<32> {
+        let tree = Self::get_tree(env.clone());
+        assert_with_error!(
+            &env,
+            start < end && end as u64 <= tree.count,
+            Error::InvalidRange
+        );
+
+        let mut leaves = vec![&env];
+        for i in start..end {
+            leaves.push_back(Self::stored_leaf(env.clone(), i as u64));
+        }
+        return MerkleTree::padded_root(env, leaves);
+    }
+
+    /// Returns the Keccak and the SHA-256 root of the stored leaves, for
+    /// verifiers that only have one of the two. Both trees have the
+    /// configured depth and empty leaf. The Keccak one is the tree itself
+    /// unless it is configured with `Sha256`, then it is a `Keccak256` tree.
+    /// Requires `store_leaves`.
+    pub fn dual_root(env: Env) -> (BytesN<32>, BytesN<32>) {
+        let config = Self::get_config(env.clone());
+        let count = Self::get_tree(env.clone()).count;
+        let keccak_algo = match config.hash_algo {
+            HashAlgo::Sha256 => HashAlgo::Keccak256,
+            hash_algo => hash_algo,
+        };
+
+        let mut keccak = MerkleTree::with_hash_algo(&env, config.depth, keccak_algo);
+        let mut sha256 = MerkleTree::with_hash_algo(&env, config.depth, HashAlgo::Sha256);
+        for i in 0..count {
+            let leaf = Self::stored_leaf(env.clone(), i);
+            keccak.insert(env.clone(), leaf.clone());
+            sha256.insert(env.clone(), leaf);
+        }
+        return (
+            Self::tree_root(env.clone(), &keccak),
+            Self::tree_root(env, &sha256),
+        );
+    }
+
+    /// Compares the tree against a full leaf set held by the caller. When the
+    /// roots differ and leaves are stored, also reports where the supplied
+    /// leaves first diverge from the stored ones.
+    pub fn audit_against(env: Env, leaves: Vec<BytesN<32>>) -> AuditResult {
+        let tree = Self::get_tree(env.clone());
+        let expected_root = Self::tree_root(env.clone(), &tree);
+
+        let mut supplied = Self::empty_tree(env.clone());
+        for leaf in leaves.iter() {
+            supplied.insert(env.clone(), leaf);
+        }
+        let computed_root = Self::tree_root(env.clone(), &supplied);
+        let matches = computed_root == expected_root;
+
+        let mut first_divergence = None;
+        if !matches && Self::get_config(env.clone()).store_leaves {
+            let common = u64::min(leaves.len() as u64, tree.count) as u32;
+            first_divergence = (0..common)
+                .find(|i| leaves.get_unchecked(*i) != Self::stored_leaf(env.clone(), *i as u64))
+                .or(if leaves.len() as u64 != tree.count {
+                    Some(common)
+                } else {
+                    None
+                });
+        }
+
+        return AuditResult {
+            matches,
+            computed_root,
+            expected_root,
+            first_divergence,
+        };
+    }
+
+    /// Returns whether `leafset_hash` is the keccak256 of every stored leaf
+    /// concatenated in insertion order, independent of the root. Requires
+    /// `store_leaves`.
+    pub fn verify_leafset_commitment(env: Env, leafset_hash: BytesN<32>) -> bool {
+        let tree = Self::get_tree(env.clone());
+
+        let mut leaves = vec![&env];
+        for i in 0..tree.count {
+            leaves.push_back(Self::stored_leaf(env.clone(), i).to_array());
+        }
+        return MerkleTree::keccak256(leaves) == leafset_hash.to_array();
+    }
+
+    /// Returns the root of the empty subtree right after the last leaf: the
+    /// aligned subtree of `2^trailing_zeros(count)` leaves starting at index
+    /// `count`, i.e. the zero hash of level `trailing_zeros(count)`. It is
+    /// the largest empty subtree a proof for the next leaves can take whole.
+    /// With `count == 0` that is the whole tree and the empty tree root.
+    pub fn empty_suffix_root(env: Env) -> BytesN<32> {
+        let tree = Self::get_tree(env.clone());
+        if tree.count == 0 {
+            return Self::tree_root(env, &tree);
+        }
+
+        assert_with_error!(&env, !tree.is_full(), Error::MerkleTreeFull);
+
+        let _zeroes = Self::zeroes(env.clone());
+        return _zeroes.get_unchecked(tree.count.trailing_zeros());
+    }
+
+    /// Returns the current root bound to a chain and contract, so it can't be
+    /// replayed as another deployment's root. The encoding is
+    /// `keccak256([chain_id, contract_tag, root])` with `chain_id` as a
+    /// 32-byte big-endian word.
+    pub fn domain_bound_root(env: Env, chain_id: u32, contract_tag: BytesN<32>) -> BytesN<32> {
+        let root = Self::get_root(env.clone());
+        let _vec = vec![
+            &env,
+            MerkleTree::to_word(chain_id as u64),
+            contract_tag.to_array(),
+            root.to_array(),
+        ];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    /// Returns the left and right operands of the topmost hash, whose hash
+    /// is the current root, i.e. the roots of both halves of the tree.
+    pub fn final_root_inputs(env: Env) -> (BytesN<32>, BytesN<32>) {
+        let tree = Self::get_tree(env.clone());
+        assert_with_error!(&env, !tree.is_full(), Error::MerkleTreeFull);
+
+        let _zeroes = Self::zeroes(env.clone());
+        let top = tree.depth - 1;
+
+        // Same as the last step of `root`, the next leaf picks the half.
+        let current = tree.subtree_root_with_ctx(env.clone(), _zeroes.clone(), top);
+        if (tree.count >> top) & 0x01 == 1 {
+            return (tree.branch.get_unchecked(top), current);
+        }
+        return (current, _zeroes.get_unchecked(top));
     }
 
-    pub fn insert(env: Env, node: BytesN<32>) -> MerkleTree {
+    /// Returns whether `leaf` is in the current tree at `index`, see
+    /// `MerkleTree::branch_root`. Indices past the last leaf revert with
+    /// `MerkleTreeIndexOutOfRange`.
+    pub fn verify(env: Env, leaf: BytesN<32>, branch: Vec<BytesN<32>>, index: LeafIndex) -> bool {
+        let tree = Self::get_tree(env.clone());
+        let raw = u64::from(index);
+        assert_with_error!(
+            &env,
+            raw <= u32::MAX as u64 && tree.contains_index(raw as u32),
+            Error::MerkleTreeIndexOutOfRange
+        );
+        return Self::verify_leaf(env, leaf, branch, index);
+    }
+
+    /// Same as `verify`, also requiring `lo <= index < hi`, e.g. the window
+    /// of a page of an allowlist. Indices inside the window but past the last
+    /// leaf revert like `verify`.
+    pub fn verify_in_range(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        lo: u32,
+        hi: u32,
+    ) -> bool {
+        if u64::from(index) < lo as u64 || u64::from(index) >= hi as u64 {
+            return false;
+        }
+        return Self::verify(env, item, branch, index);
+    }
+
+    /// Returns whether `item` is the only leaf of the tree, without a proof.
+    pub fn verify_single(env: Env, item: BytesN<32>) -> bool {
+        // The only leaf sits at index 0, next to nothing but empty subtrees
+        let branch = Self::zeroes(env.clone());
+        return Self::get_count(env.clone()) == 1
+            && Self::verify_leaf(env, item, branch, LeafIndex(0));
+    }
+
+    /// Returns whether `root` is the current root or one of the roots the
+    /// last `ROOT_HISTORY_SIZE` inserts produced, a batch counting as one
+    /// insert, so a proof built against a recent root still verifies after a
+    /// few more inserts.
+    pub fn is_known_root(env: Env, root: BytesN<32>) -> bool {
+        if root == Self::get_root(env.clone()) {
+            return true;
+        }
+        for slot in 0..ROOT_HISTORY_SIZE as u32 {
+            Self::extend_entry_ttl(env.clone(), &(ROOT_HISTORY, slot));
+            let checkpoint: Option<(u64, BytesN<32>)> =
+                env.storage().persistent().get(&(ROOT_HISTORY, slot));
+            if let Some((_, known)) = checkpoint {
+                if known == root {
+                    return true;
+                }
+            }
+        }
+        return false;
+    }
+
+    /// Returns the current root with what it takes to check a proof against
+    /// it off-chain, in one call.
+    pub fn verification_bundle(env: Env) -> VerificationBundle {
+        let tree = Self::get_tree(env.clone());
+        let config = Self::get_config(env.clone());
+        return VerificationBundle {
+            root: Self::tree_root(env.clone(), &tree),
+            count: tree.count(),
+            depth: tree.depth(),
+            hash_algo: tree.hash_algo(),
+            zero_hashes: Self::zeroes(env),
+            bind_index: config.bind_index,
+        };
+    }
+
+    /// Returns the rule leaves are committed with, so clients can build the
+    /// same leaves off-chain.
+    pub fn leaf_rule(env: Env) -> LeafRule {
+        let config = Self::get_config(env.clone());
+        let prefix = match config.hash_algo {
+            HashAlgo::Keccak256Prefixed => Bytes::from_array(&env, &[0x00]),
+            _ => Bytes::new(&env),
+        };
+        return LeafRule {
+            prefix,
+            salt: config.salt,
+            bind_index: config.bind_index,
+        };
+    }
+
+    /// Returns the leaf count and root of the last checkpoint, which is the
+    /// current tree. Before any insert that is `(0, empty root)`.
+    pub fn latest_checkpoint(env: Env) -> (u64, BytesN<32>) {
+        let tree = Self::get_tree(env.clone());
+        let slot = (tree.count % ROOT_HISTORY_SIZE) as u32;
+        Self::extend_entry_ttl(env.clone(), &(ROOT_HISTORY, slot));
+        return env
+            .storage()
+            .persistent()
+            .get(&(ROOT_HISTORY, slot))
+            .unwrap_or((tree.count, Self::tree_root(env, &tree)));
+    }
+
+    /// Returns whether `root` is the root of an empty tree of the configured
+    /// depth and empty leaf. That root is not all zeros, so it can be mistaken
+    /// for the root of a populated tree.
+    pub fn is_empty_root(env: Env, root: BytesN<32>) -> bool {
+        let empty = Self::empty_tree(env.clone());
+        return root == Self::tree_root(env, &empty);
+    }
+
+    /// Returns whether the tree holds no leaf, checking along the way that
+    /// its root is the empty root. Fails with `StateCorrupted` if an empty
+    /// count comes with any other root.
+    pub fn prove_empty(env: Env) -> bool {
+        let tree = Self::get_tree(env.clone());
+        if tree.count != 0 {
+            return false;
+        }
+
+        let root = Self::tree_root(env.clone(), &tree);
+        assert_with_error!(
+            &env,
+            Self::is_empty_root(env.clone(), root),
+            Error::StateCorrupted
+        );
+        return true;
+    }
+
+    /// Returns how many hashes `insert` has performed on the binary tree so
+    /// far, whatever the hash function: the prefixed leaf hash and the index
+    /// binding of every leaf, if configured, and one per level it carries
+    /// through. Root and first leaf path computations are not counted.
+    pub fn hash_ops(env: Env) -> u64 {
+        Self::extend_entry_ttl(env.clone(), &HASH_OPS);
+        return env.storage().persistent().get(&HASH_OPS).unwrap_or(0);
+    }
+
+    /// Returns how many hashes inserting `n` more leaves would add to
+    /// `hash_ops`, e.g. as one `insert_hashed` batch (hashing
+    /// the preimages themselves is not included). Fails with
+    /// `MerkleTreeFull` if the tree cannot hold them.
+    pub fn batch_cost(env: Env, n: u32) -> u32 {
+        let tree = Self::get_tree(env.clone());
+        assert_with_error!(
+            &env,
+            n as u64 <= tree.remaining_capacity(),
+            Error::MerkleTreeFull
+        );
+        let end = tree.count + n as u64;
+
+        // Insert number `c` costs `trailing_zeros(c)`, and those sum up to
+        // `m - popcount(m)` over `1..=m`.
+        let carries = |m: u64| m - m.count_ones() as u64;
+        let leaves = n as u64 * Self::leaf_hash_ops(env.clone(), &tree);
+        return (carries(end) - carries(tree.count) + leaves) as u32;
+    }
+
+    /// Verifies a leaf against the current root, accepting either the leaf
+    /// itself or its preimage. A 32-byte input is always taken as the leaf;
+    /// a 32-byte preimage must therefore be hashed by the caller first.
+    pub fn verify_flexible(
+        env: Env,
+        leaf_or_preimage: Bytes,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let leaf: BytesN<32> = if leaf_or_preimage.len() == 32 {
+            leaf_or_preimage.try_into().expect("Error to convert leaf")
+        } else {
+            let hash = MerkleTree::keccak256_bytes(&leaf_or_preimage);
+            BytesN::from_array(&env, &hash)
+        };
+
+        return Self::verify_leaf(env, leaf, branch, index);
+    }
+
+    /// Verifies `item` against the current root, completing a short branch
+    /// with the zero hash of every missing level. Siblings of the top levels
+    /// a small tree hasn't reached can then be left out, while `verify`
+    /// rejects a short branch.
+    pub fn verify_lenient(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let _zeroes = Self::zeroes(env.clone());
+        let mut _branch = branch;
+        for level in _branch.len().._zeroes.len() {
+            _branch.push_back(_zeroes.get_unchecked(level));
+        }
+        return Self::verify_leaf(env, item, _branch, index);
+    }
+
+    /// Verifies `inner_leaf` in a tree whose root is a leaf of this one, e.g.
+    /// a shallower tree of another contract. The inner tree is as deep as
+    /// `inner_branch` is long and built with the same configuration, hash and
+    /// leaf rule included, and its root is checked like `verify` at
+    /// `outer_index`. Fails with `InvalidDepth` for an empty or too long
+    /// `inner_branch`.
+    pub fn verify_nested(
+        env: Env,
+        inner_leaf: BytesN<32>,
+        inner_branch: Vec<BytesN<32>>,
+        inner_index: LeafIndex,
+        outer_branch: Vec<BytesN<32>>,
+        outer_index: LeafIndex,
+    ) -> bool {
+        let depth = inner_branch.len();
+        assert_with_error!(
+            &env,
+            (1..=MAX_SUPPORTED_DEPTH).contains(&depth),
+            Error::InvalidDepth
+        );
+
+        let inner_root =
+            Self::leaf_root_at_depth(env.clone(), inner_leaf, inner_branch, inner_index, depth);
+        return Self::verify_leaf(env, inner_root, outer_branch, outer_index);
+    }
+
+    /// Verifies `item` against the current root using 64-byte siblings, as
+    /// produced by proof formats that attach metadata to every node. The
+    /// first 32 bytes of each sibling are the node hash, the last 32 bytes are
+    /// metadata and are ignored.
+    pub fn verify_wide(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<64>>,
+        index: LeafIndex,
+    ) -> bool {
+        assert_with_error!(
+            &env,
+            branch.len() <= Self::get_config(env.clone()).depth,
+            Error::MerkleTreeInvalidVecSize
+        );
+
+        let mut _branch = vec![&env];
+        for wide in branch {
+            let mut node = [0; 32];
+            node.copy_from_slice(&wide.to_array()[..32]);
+            _branch.push_back(BytesN::from_array(&env, &node));
+        }
+
+        return Self::verify_leaf(env, item, _branch, index);
+    }
+
+    /// Verifies the consecutive `leaves` starting at `start_index` against
+    /// the current root with one shared proof. Going up, `left_path` holds
+    /// the left sibling of the leftmost node of every level where that node
+    /// is a right child, and `right_path` the right sibling of the rightmost
+    /// node where it is a left child, both leaf level first. Every entry
+    /// must be used up, and the range must end at or before the last leaf.
+    pub fn verify_range(
+        env: Env,
+        leaves: Vec<BytesN<32>>,
+        start_index: u32,
+        left_path: Vec<BytesN<32>>,
+        right_path: Vec<BytesN<32>>,
+    ) -> bool {
+        let tree = Self::get_tree(env.clone());
+        if leaves.is_empty() || start_index as u64 + leaves.len() as u64 > tree.count {
+            return false;
+        }
+
+        let algo = tree.hash_algo();
+        let mut _nodes = vec![&env];
+        for (i, leaf) in leaves.iter().enumerate() {
+            let index = start_index as u64 + i as u64;
+            let leaf = Self::committed_leaf(env.clone(), index, leaf);
+            _nodes.push_back(algo.hash_leaf(&env, &leaf));
+        }
+        let mut _start = start_index;
+        let mut _left = left_path.iter();
+        let mut _right = right_path.iter();
+        for _ in 0..tree.depth {
+            if _start & 1 == 1 {
+                match _left.next() {
+                    Some(sibling) => _nodes.push_front(sibling),
+                    None => return false,
+                }
+                _start -= 1;
+            }
+            if _nodes.len() & 1 == 1 {
+                match _right.next() {
+                    Some(sibling) => _nodes.push_back(sibling),
+                    None => return false,
+                }
+            }
+
+            let mut _parents = vec![&env];
+            for i in (0.._nodes.len()).step_by(2) {
+                let left = _nodes.get_unchecked(i);
+                let right = _nodes.get_unchecked(i + 1);
+                _parents.push_back(MerkleTree::hash_pair(&env, algo, &left, &right));
+            }
+            _nodes = _parents;
+            _start >>= 1;
+        }
+
+        return _left.next().is_none()
+            && _right.next().is_none()
+            && _nodes.get_unchecked(0) == Self::tree_root(env, &tree);
+    }
+
+    /// Verifies a leaf against the current root with `branch` ordered from
+    /// the root level down. Unlike leaf-up proofs it can't have its top
+    /// levels omitted, so it must hold all `depth` siblings, and reverts
+    /// with `MerkleTreeInvalidVecSize` otherwise like `verify` does.
+    pub fn verify_top_down(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        assert_with_error!(
+            &env,
+            branch.len() == Self::get_config(env.clone()).depth,
+            Error::MerkleTreeInvalidVecSize
+        );
+
+        let mut leaf_up = vec![&env];
+        for sibling in branch.iter() {
+            leaf_up.push_front(sibling);
+        }
+        return Self::verify_leaf(env, item, leaf_up, index);
+    }
+
+    /// Verifies a value inserted with `insert_salted` against the current root.
+    pub fn verify_salted(
+        env: Env,
+        value: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let leaf = Self::salted_leaf(env.clone(), value);
+        return Self::verify_leaf(env, leaf, branch, index);
+    }
+
+    /// Verifies `item` like `verify_flexible` does, but reports the computed
+    /// and expected roots and, on a tree storing its leaves, the first level
+    /// where the supplied path leaves the actual one.
+    pub fn verify_diagnostic(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> VerifyResult {
+        let config = Self::get_config(env.clone());
+        let leaf = Self::committed_leaf(env.clone(), u64::from(index), item);
+        let path = MerkleTree::branch_path_with(
+            env.clone(),
+            config.hash_algo,
+            config.depth,
+            leaf,
+            branch,
+            index,
+        );
+        let computed_root = path.get_unchecked(config.depth);
+        let expected_root = Self::get_root(env.clone());
+        let valid =
+            computed_root == expected_root && u64::from(index) < Self::get_count(env.clone());
+
+        let mut divergence_level = None;
+        if !valid && config.store_leaves {
+            let (_, reference) = Self::stored_proof(env.clone(), u64::from(index));
+            for level in 0..=config.depth {
+                if path.get_unchecked(level) != reference.get_unchecked(level) {
+                    divergence_level = Some(level);
+                    break;
+                }
+            }
+        }
+
+        return VerifyResult {
+            valid,
+            computed_root,
+            expected_root,
+            divergence_level,
+        };
+    }
+
+    /// Verifies `item` against the current root only if the tree is built
+    /// with `expected_algo`, so a client never accepts a result computed with
+    /// a hash function it did not expect.
+    pub fn verify_checked(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        expected_algo: HashAlgo,
+    ) -> bool {
+        if Self::get_config(env.clone()).hash_algo != expected_algo {
+            return false;
+        }
+        return Self::verify_leaf(env, item, branch, index);
+    }
+
+    /// Returns whether every proof verifies against the current root like
+    /// `verify_leaf` checks it, stopping at the first one that does not.
+    pub fn contains_all(env: Env, proofs: Vec<MerkleProof>) -> bool {
+        for proof in proofs {
+            if !Self::verify_leaf(env.clone(), proof.leaf, proof.branch, proof.index) {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    /// Verifies that `node` was inserted with `insert_bound` by `sender`.
+    pub fn verify_bound(
+        env: Env,
+        sender: Address,
+        node: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let leaf = Self::bound_leaf(env.clone(), sender, node);
+        return Self::verify_leaf(env, leaf, branch, index);
+    }
+
+    /// Verifies that `leaf_a` and `leaf_b` are both in the tree at the
+    /// consecutive indices `index_a` and `index_a + 1`.
+    pub fn verify_adjacent(
+        env: Env,
+        leaf_a: BytesN<32>,
+        index_a: LeafIndex,
+        proof_a: Vec<BytesN<32>>,
+        leaf_b: BytesN<32>,
+        proof_b: Vec<BytesN<32>>,
+    ) -> bool {
+        let count = Self::get_tree(env.clone()).count;
+        let index_b = match LeafIndex::new(u64::from(index_a).saturating_add(1), count) {
+            Some(index_b) => index_b,
+            None => return false,
+        };
+        return Self::verify_leaf(env.clone(), leaf_a, proof_a, index_a)
+            && Self::verify_leaf(env, leaf_b, proof_b, index_b);
+    }
+
+    /// Verifies `item` against the root of a past state given by its
+    /// frontier and count, e.g. a snapshot of `get_tree` taken earlier.
+    pub fn verify_with_frontier(
+        env: Env,
+        frontier: Vec<BytesN<32>>,
+        count: u64,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> bool {
+        let tree = Self::tree_from_frontier(env.clone(), frontier, count);
+        let root = Self::tree_root(env.clone(), &tree);
+        return Self::verify_leaf_at(env, item, branch, index, count, root);
+    }
+
+    /// Verifies `item` at `index` against both `root_old` and `root_new`,
+    /// each with its own proof, so its membership held across the root
+    /// transition. Both roots are supplied, neither has to be the current one.
+    pub fn verify_continuity(
+        env: Env,
+        item: BytesN<32>,
+        branch_old: Vec<BytesN<32>>,
+        index: LeafIndex,
+        root_old: BytesN<32>,
+        branch_new: Vec<BytesN<32>>,
+        root_new: BytesN<32>,
+    ) -> bool {
+        return Self::leaf_root(env.clone(), item.clone(), branch_old, index) == root_old
+            && Self::leaf_root(env, item, branch_new, index) == root_new;
+    }
+
+    /// Verifies `item` against a position-bound `root` of a tree of the
+    /// configured depth, see `MerkleTree::position_bound_branch_root`. The
+    /// tree stored by this contract is not position-bound, so the root must
+    /// be supplied. Fails with `MerkleTreeInvalidVecSize` unless `branch`
+    /// holds one sibling per level.
+    pub fn verify_position_bound(
+        env: Env,
+        item: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        root: BytesN<32>,
+    ) -> bool {
+        let depth = Self::get_config(env.clone()).depth;
+        let computed = MerkleTree::position_bound_branch_root(env, depth, item, branch, index);
+        return computed == root;
+    }
+}
+
+#[cfg(feature = "helper-contract")]
+impl Contract {
+    /// Inserts `node` and updates everything derived from the inserted leaves.
+    fn append(env: Env, node: BytesN<32>) -> MerkleTree {
+        let (tree, _) = Self::append_indexed(env, node);
+        return tree;
+    }
+
+    /// Same as `append`, also returning the index `node` was inserted at.
+    fn append_indexed(env: Env, node: BytesN<32>) -> (MerkleTree, u32) {
         let mut tree = Self::get_tree(env.clone());
+        let previous_root = Self::tree_root(env.clone(), &tree);
 
-        tree.insert(env.clone(), node);
+        let node = Self::committed_leaf(env.clone(), tree.count, node);
+        if tree.count > 0 && (tree.count + 1).is_power_of_two() {
+            Self::record_first_path(env.clone(), &tree, node.clone());
+        }
+        let index = tree.insert(env.clone(), node.clone());
 
         // Save the tree.
-        env.storage().instance().set(&TREE, &tree);
+        Self::save_tree(env.clone(), &tree);
+
+        if Self::get_config(env.clone()).fanout != 2 {
+            let mut kary = Self::kary_tree(env.clone());
+            kary.insert(env.clone(), node.clone());
+            env.storage().instance().set(&KARY_TREE, &kary);
+        }
+
+        if Self::get_config(env.clone()).store_leaves {
+            let key = (LEAF, (tree.count - 1) as u32);
+            env.storage().persistent().set(&key, &node);
+            Self::extend_entry_ttl(env.clone(), &key);
+        }
+
+        // An insert hashes its leaf, then once per level it carries through,
+        // which is the number of trailing zeros of the new count.
+        let ops = Self::leaf_hash_ops(env.clone(), &tree) + tree.count.trailing_zeros() as u64;
+        Self::add_hash_ops(env.clone(), ops);
+
+        let event = InsertEvent {
+            index,
+            leaf: node,
+            previous_root,
+            root: Self::tree_root(env.clone(), &tree),
+        };
+        env.events().publish((INSERT,), event);
+        Self::record_root(env.clone(), &tree);
+
+        return (tree, index);
+    }
+
+    /// Same as `append` for every node in order, but saves the tree, records
+    /// its root and publishes an `ins_batch` event once for the whole batch.
+    /// Returns the index of the first node with the number inserted.
+    fn append_batch(env: Env, nodes: Vec<BytesN<32>>) -> (u64, u32) {
+        let config = Self::get_config(env.clone());
+        let mut tree = Self::get_tree(env.clone());
+        let start = tree.count;
+        if nodes.is_empty() {
+            return (start, 0);
+        }
+        assert_with_error!(
+            &env,
+            nodes.len() as u64 <= tree.remaining_capacity(),
+            Error::MerkleTreeFull
+        );
+        let previous_root = Self::tree_root(env.clone(), &tree);
+
+        let mut leaves = vec![&env];
+        for (i, node) in nodes.iter().enumerate() {
+            let index = tree.count + i as u64;
+            leaves.push_back(Self::committed_leaf(env.clone(), index, node));
+        }
+
+        // `record_first_path` reads the frontier right before the insert
+        // that reaches a power of two, so the batch is only split there.
+        let mut offset = 0;
+        while offset < leaves.len() {
+            let next = (tree.count + 1).next_power_of_two();
+            let end = u64::min(leaves.len() as u64, offset as u64 + next - tree.count) as u32;
+            tree.insert_batch(env.clone(), leaves.slice(offset..end - 1));
+            let last = leaves.get_unchecked(end - 1);
+            if tree.count > 0 && tree.count + 1 == next {
+                Self::record_first_path(env.clone(), &tree, last.clone());
+            }
+            tree.insert(env.clone(), last);
+            offset = end;
+        }
+        Self::save_tree(env.clone(), &tree);
+
+        if config.fanout != 2 {
+            let mut kary = Self::kary_tree(env.clone());
+            for leaf in leaves.iter() {
+                kary.insert(env.clone(), leaf);
+            }
+            env.storage().instance().set(&KARY_TREE, &kary);
+        }
+
+        if config.store_leaves {
+            for (i, leaf) in leaves.iter().enumerate() {
+                let key = (LEAF, (start + i as u64) as u32);
+                env.storage().persistent().set(&key, &leaf);
+                Self::extend_entry_ttl(env.clone(), &key);
+            }
+        }
+
+        // Every insert of the batch hashes its leaf, then once per level it
+        // carries through.
+        let leaf_ops = Self::leaf_hash_ops(env.clone(), &tree);
+        let ops = (start + 1..=tree.count)
+            .map(|count| leaf_ops + count.trailing_zeros() as u64)
+            .sum();
+        Self::add_hash_ops(env.clone(), ops);
 
+        let event = BatchInsertEvent {
+            start,
+            leaves: leaves.clone(),
+            previous_root,
+            root: Self::tree_root(env.clone(), &tree),
+        };
+        env.events().publish((INSERT_BATCH,), event);
+        Self::record_root(env.clone(), &tree);
+
+        return (start, leaves.len());
+    }
+
+    fn namespace_tree(env: Env, namespace: Symbol) -> MerkleTree {
+        let key = (TREE, namespace);
+        return match env.storage().persistent().get(&key) {
+            Some(tree) => tree,
+            None => env
+                .storage()
+                .instance()
+                .get(&key)
+                .unwrap_or_else(|| Self::empty_tree(env.clone())),
+        };
+    }
+
+    /// Writes the tree to persistent storage, dropping the copy an older
+    /// version kept in instance storage.
+    fn save_tree(env: Env, tree: &MerkleTree) {
+        env.storage().persistent().set(&TREE, tree);
+        env.storage().instance().remove(&TREE);
+        Self::extend_ttl(env);
+    }
+
+    /// Tree of the configured shape holding the state `frontier` and `count`
+    /// describe, e.g. a snapshot of `get_tree` taken earlier.
+    fn tree_from_frontier(env: Env, frontier: Vec<BytesN<32>>, count: u64) -> MerkleTree {
+        let mut tree = Self::empty_tree(env);
+        tree.branch = frontier;
+        tree.count = count;
         return tree;
     }
 
-    pub fn get_root(env: Env) -> BytesN<32> {
+    /// Empty tree of the configured depth.
+    fn empty_tree(env: Env) -> MerkleTree {
+        let config = Self::get_config(env.clone());
+        return MerkleTree::with_hash_algo(&env, config.depth, config.hash_algo);
+    }
+
+    /// Zero hash tower of the configured empty leaf, one entry per level of
+    /// the configured depth.
+    fn zeroes(env: Env) -> Vec<BytesN<32>> {
+        let hash_algo = Self::get_config(env.clone()).hash_algo;
+        return Self::zeroes_with(env, hash_algo);
+    }
+
+    /// Same as `zeroes` hashing the levels with `hash_algo`.
+    fn zeroes_with(env: Env, hash_algo: HashAlgo) -> Vec<BytesN<32>> {
+        let config = Self::get_config(env.clone());
+        let zeroes = match config.empty_leaf {
+            Some(empty_leaf) => MerkleTree::zero_hashes_from_with(env, hash_algo, empty_leaf),
+            None => MerkleTree::zero_hashes_at(env, hash_algo, TREE_DEPTH as u32),
+        };
+        return zeroes.slice(0..config.depth);
+    }
+
+    fn tree_root(env: Env, tree: &MerkleTree) -> BytesN<32> {
+        let empty_leaf = Self::get_config(env.clone())
+            .empty_leaf
+            .unwrap_or(BytesN::from_array(&env, &[0; 32]));
+        let zeroes = Self::zeroes_with(env.clone(), tree.hash_algo());
+        return tree.root_with_ctx(env, &empty_leaf, zeroes);
+    }
+
+    fn get_mmr(env: Env) -> Mmr {
+        return env.storage().instance().get(&MMR).unwrap_or(Mmr::new(&env));
+    }
+
+    fn kary_tree(env: Env) -> KaryTree {
+        return env
+            .storage()
+            .instance()
+            .get(&KARY_TREE)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::InvalidFanout));
+    }
+
+    fn require_open(env: Env) {
+        assert_with_error!(
+            &env,
+            !Self::get_config(env.clone()).permissioned,
+            Error::Unauthorized
+        );
+    }
+
+    fn require_inserter(env: Env, inserter: Address) {
+        inserter.require_auth();
+        if Self::get_config(env.clone()).permissioned {
+            assert_with_error!(
+                &env,
+                Self::is_inserter(env.clone(), inserter),
+                Error::Unauthorized
+            );
+        }
+    }
+
+    fn require_admin(env: Env) {
+        let admin = Self::get_config(env.clone())
+            .admin
+            .unwrap_or_else(|| panic_with_error!(&env, Error::AdminNotSet));
+        admin.require_auth();
+    }
+
+    fn stored_leaf(env: Env, index: u64) -> BytesN<32> {
+        assert_with_error!(
+            &env,
+            Self::get_config(env.clone()).store_leaves,
+            Error::LeavesNotStored
+        );
+        Self::extend_entry_ttl(env.clone(), &(LEAF, index as u32));
+        return env
+            .storage()
+            .persistent()
+            .get(&(LEAF, index as u32))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::LeavesNotStored));
+    }
+
+    /// Rebuilds, from the stored leaves, the proof of `index` and the actual
+    /// node at every level of its path, the last one being the root.
+    fn stored_proof(env: Env, index: u64) -> (Vec<BytesN<32>>, Vec<BytesN<32>>) {
         let tree = Self::get_tree(env.clone());
-        let root = tree.root(env.clone());
-        return root;
+        let algo = tree.hash_algo();
+        let _zeroes = Self::zeroes(env.clone());
+
+        let mut nodes = vec![&env];
+        for i in 0..tree.count {
+            nodes.push_back(algo.hash_leaf(&env, &Self::stored_leaf(env.clone(), i)));
+        }
+
+        let mut branch = vec![&env];
+        let mut path = vec![&env];
+        for level in 0..tree.depth {
+            let position = u32::try_from(index >> level).unwrap_or(u32::MAX);
+            let zero = _zeroes.get_unchecked(level);
+            path.push_back(nodes.get(position).unwrap_or(zero.clone()));
+            branch.push_back(nodes.get(position ^ 1).unwrap_or(zero.clone()));
+
+            let mut parents = vec![&env];
+            for i in (0..nodes.len()).step_by(2) {
+                let left = nodes.get_unchecked(i);
+                let right = nodes.get(i + 1).unwrap_or(zero.clone());
+                parents.push_back(MerkleTree::hash_pair(&env, algo, &left, &right));
+            }
+            nodes = parents;
+        }
+        // The path starts at the leaf as given to `verify`, before `hash_leaf`
+        if index < tree.count {
+            path.set(0, Self::stored_leaf(env.clone(), index));
+        }
+        path.push_back(Self::tree_root(env, &tree));
+        return (branch, path);
+    }
+
+    /// Leaves past the last one don't exist yet, so they never verify, not
+    /// even as the empty leaf the tree holds there.
+    fn verify_leaf(env: Env, leaf: BytesN<32>, branch: Vec<BytesN<32>>, index: LeafIndex) -> bool {
+        let tree = Self::get_tree(env.clone());
+        let root = Self::tree_root(env.clone(), &tree);
+        return Self::verify_leaf_at(env, leaf, branch, index, tree.count, root);
+    }
+
+    /// Same as `verify_leaf` against `root`, a root the tree had when it
+    /// held `count` leaves.
+    fn verify_leaf_at(
+        env: Env,
+        leaf: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        count: u64,
+        root: BytesN<32>,
+    ) -> bool {
+        // Still check the proof first, so a malformed one fails with its error
+        let valid = Self::leaf_root(env, leaf, branch, index) == root;
+        return valid && u64::from(index) < count;
+    }
+
+    /// Root `branch` leads to from the leaf committed for `leaf` at `index`,
+    /// with the configured depth and hash.
+    fn leaf_root(
+        env: Env,
+        leaf: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+    ) -> BytesN<32> {
+        let depth = Self::get_config(env.clone()).depth;
+        return Self::leaf_root_at_depth(env, leaf, branch, index, depth);
+    }
+
+    /// Same as `leaf_root` in a tree of `depth` levels.
+    fn leaf_root_at_depth(
+        env: Env,
+        leaf: BytesN<32>,
+        branch: Vec<BytesN<32>>,
+        index: LeafIndex,
+        depth: u32,
+    ) -> BytesN<32> {
+        let hash_algo = Self::get_config(env.clone()).hash_algo;
+        let leaf = Self::committed_leaf(env.clone(), u64::from(index), leaf);
+        return MerkleTree::branch_root_with(env, hash_algo, depth, leaf, branch, index);
+    }
+
+    /// Encodes `count` as a 32-byte word in the configured byte order.
+    fn count_word(env: Env, count: u64) -> [u8; 32] {
+        let mut _word = [0; 32];
+        match Self::get_config(env).count_endianness {
+            Endianness::Big => _word[24..].copy_from_slice(&count.to_be_bytes()),
+            Endianness::Little => _word[..8].copy_from_slice(&count.to_le_bytes()),
+        }
+        return _word;
+    }
+
+    /// Keeps the root of `tree` as the checkpoint of its count, overwriting
+    /// the one `ROOT_HISTORY_SIZE` inserts older.
+    fn record_root(env: Env, tree: &MerkleTree) {
+        let slot = (tree.count % ROOT_HISTORY_SIZE) as u32;
+        let checkpoint = (tree.count, Self::tree_root(env.clone(), tree));
+        env.storage()
+            .persistent()
+            .set(&(ROOT_HISTORY, slot), &checkpoint);
+        Self::extend_entry_ttl(env, &(ROOT_HISTORY, slot));
+    }
+
+    /// Keeps the persistent entry at `key` alive for `ttl_extension` more
+    /// ledgers whenever it is read or written. Nothing without a policy or
+    /// if the entry doesn't exist.
+    fn extend_entry_ttl<K: IntoVal<Env, Val>>(env: Env, key: &K) {
+        if let Some(ledgers) = Self::get_config(env.clone()).ttl_extension {
+            if env.storage().persistent().has(key) {
+                env.storage().persistent().bump(key, ledgers);
+            }
+        }
+    }
+
+    /// Keeps the contract state alive for `ttl_extension` more ledgers, and
+    /// the tree entry for `TREE_TTL` ledgers without it.
+    fn extend_ttl(env: Env) {
+        let config = Self::get_config(env.clone());
+        if let Some(ledgers) = config.ttl_extension {
+            env.storage().instance().bump(ledgers);
+        }
+        if env.storage().persistent().has(&TREE) {
+            let ledgers = config.ttl_extension.unwrap_or(TREE_TTL);
+            env.storage().persistent().bump(&TREE, ledgers);
+        }
+    }
+
+    /// Returns the leaf committed for `node` at `index`, bound to the index
+    /// if the config says so.
+    fn committed_leaf(env: Env, index: u64, node: BytesN<32>) -> BytesN<32> {
+        if !Self::get_config(env.clone()).bind_index {
+            return node;
+        }
+        let _vec = vec![&env, MerkleTree::to_word(index), node.to_array()];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    fn salted_leaf(env: Env, value: BytesN<32>) -> BytesN<32> {
+        let salt = Self::get_config(env.clone())
+            .salt
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SaltNotSet));
+        let _vec = vec![&env, salt.to_array(), value.to_array()];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    fn bound_leaf(env: Env, sender: Address, node: BytesN<32>) -> BytesN<32> {
+        let sender_hash = MerkleTree::keccak256_bytes(&sender.to_xdr(&env));
+        let _vec = vec![&env, sender_hash, node.to_array()];
+        return BytesN::from_array(&env, &MerkleTree::keccak256(_vec));
+    }
+
+    /// Stores the sibling of index 0 completed by inserting `leaf` into
+    /// `tree`, that is the root of the right half of the subtree the insert
+    /// fills. Must run before the insert, which clears the frontier below.
+    fn record_first_path(env: Env, tree: &MerkleTree, leaf: BytesN<32>) {
+        let level = (tree.count + 1).trailing_zeros() - 1;
+        let algo = tree.hash_algo();
+        let mut node = algo.hash_leaf(&env, &leaf);
+        for i in 0..level {
+            let left = tree.branch.get_unchecked(i);
+            node = MerkleTree::hash_pair(&env, algo, &left, &node);
+        }
+
+        let mut path: Vec<BytesN<32>> = env
+            .storage()
+            .persistent()
+            .get(&FIRST_PATH)
+            .unwrap_or(vec![&env]);
+        path.push_back(node);
+        env.storage().persistent().set(&FIRST_PATH, &path);
+        Self::extend_entry_ttl(env, &FIRST_PATH);
+    }
+
+    /// Hashes an insert spends on its leaf before carrying: the index
+    /// binding of `committed_leaf` and the prefixed leaf hash of the tree.
+    fn leaf_hash_ops(env: Env, tree: &MerkleTree) -> u64 {
+        let config = Self::get_config(env);
+        let prefixed = tree.hash_algo() == HashAlgo::Keccak256Prefixed;
+        return config.bind_index as u64 + prefixed as u64;
+    }
+
+    fn add_hash_ops(env: Env, ops: u64) {
+        if ops == 0 {
+            return;
+        }
+        let total = Self::hash_ops(env.clone()) + ops;
+        env.storage().persistent().set(&HASH_OPS, &total);
+        Self::extend_entry_ttl(env, &HASH_OPS);
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "helper-contract"))]
 mod tests;
@@ -0,0 +1,134 @@
+//! Merkle Mountain Range, an append-only accumulator with no fixed depth.
+//!
+//! The leaves are split into perfect trees ("peaks"), one per bit set in the
+//! leaf count, from the largest on the left to the smallest on the right.
+//! Appending merges equal-height peaks, and the root bags the peaks from
+//! right to left: `keccak256([peak_0, keccak256([peak_1, ...])])`.
+//!
+//! A node is identified by its height and its position among the nodes of
+//! that height, leaves being height 0. Peaks are aligned, so the node at
+//! `(height, position)` covers leaves `[position << height, (position + 1) << height)`.
+use soroban_sdk::{assert_with_error, contracttype, vec, BytesN, Env, Vec};
+
+use crate::{Error, MerkleTree};
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mmr {
+    /// Peak roots, left to right.
+    peaks: Vec<BytesN<32>>,
+    count: u64,
+}
+
+/// Proof of a leaf of a `Mmr`: its siblings up to its peak, leaf level
+/// first, and every peak of the range.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MmrProof {
+    pub index: u64,
+    pub siblings: Vec<BytesN<32>>,
+    pub peaks: Vec<BytesN<32>>,
+}
+
+impl Mmr {
+    pub fn new(env: &Env) -> Mmr {
+        return Mmr {
+            peaks: vec![env],
+            count: 0,
+        };
+    }
+
+    pub fn count(&self) -> u64 {
+        return self.count;
+    }
+
+    pub fn peaks(&self) -> Vec<BytesN<32>> {
+        return self.peaks.clone();
+    }
+
+    /**
+     * @notice Appends `_leaf` and merges the peaks it completes
+     * @param _leaf Element to append
+     * @return _created Every node created, the leaf at height 0 first, each at
+     * position `(count - 1) >> height` with the new count
+     **/
+    pub fn append(&mut self, env: &Env, _leaf: BytesN<32>) -> Vec<BytesN<32>> {
+        self.count += 1;
+
+        let mut _node = _leaf;
+        let mut _created = vec![env, _node.clone()];
+        for _ in 0..self.count.trailing_zeros() {
+            let _left = self.peaks.pop_back_unchecked();
+            _node = Self::hash_pair(env, &_left, &_node);
+            _created.push_back(_node.clone());
+        }
+        self.peaks.push_back(_node);
+        return _created;
+    }
+
+    /**
+     * @notice Bags the peaks into a single root
+     * @return Calculated root, all zeros for an empty range
+     **/
+    pub fn root(&self, env: &Env) -> BytesN<32> {
+        return Self::bag(env, &self.peaks);
+    }
+
+    /**
+     * @notice Returns which peak holds the leaf at `_index`, and its height
+     * @param _index Index of the leaf, must be below `count`
+     * @return Position of the peak in `peaks` and its height
+     **/
+    pub fn peak_of(&self, env: &Env, _index: u64) -> (u32, u32) {
+        assert_with_error!(env, _index < self.count, Error::InvalidRange);
+
+        // Peaks follow the set bits of `count`, highest first.
+        let mut _start = 0;
+        let mut _position = 0;
+        for height in (0..64).rev() {
+            if (self.count >> height) & 1 == 1 {
+                if _index < _start + (1 << height) {
+                    return (_position, height);
+                }
+                _start += 1 << height;
+                _position += 1;
+            }
+        }
+        panic!("Error no peak holds the leaf");
+    }
+
+    /**
+     * @notice Verifies `_leaf` against the root of this range
+     * @param _leaf Merkle leaf
+     * @param _proof Proof generated for this range
+     * @return Whether the proof is valid
+     **/
+    pub fn verify(&self, env: &Env, _leaf: BytesN<32>, _proof: MmrProof) -> bool {
+        if _proof.index >= self.count || _proof.peaks.len() != self.count.count_ones() {
+            return false;
+        }
+        let (_position, _height) = self.peak_of(env, _proof.index);
+        if _proof.siblings.len() != _height {
+            return false;
+        }
+
+        let _current = MerkleTree::reduce(env.clone(), _leaf, _proof.siblings, _proof.index);
+        return _proof.peaks.get_unchecked(_position) == _current
+            && Self::bag(env, &_proof.peaks) == self.root(env);
+    }
+
+    fn bag(env: &Env, _peaks: &Vec<BytesN<32>>) -> BytesN<32> {
+        let mut _root: Option<BytesN<32>> = None;
+        for peak in _peaks.iter().rev() {
+            _root = Some(match _root {
+                Some(right) => Self::hash_pair(env, &peak, &right),
+                None => peak,
+            });
+        }
+        return _root.unwrap_or(BytesN::from_array(env, &[0; 32]));
+    }
+
+    fn hash_pair(env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+        return MerkleTree::keccak256_pair(env, left, right);
+    }
+}
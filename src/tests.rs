@@ -1,7 +1,16 @@
-use crate::{Contract, ContractClient, MerkleTree};
-use soroban_sdk::{BytesN, Env, Vec, U256, vec};
+use crate::{
+    AuditResult, BatchInsertEvent, Contract, ContractClient, Endianness, Error, HashAlgo,
+    InsertEvent, InsertResult, KaryTree, LeafIndex, LeafRule, LegacyMerkleTree, MerkleHasher,
+    MerkleProof, MerkleTree, Mmr, MmrProof, PortableProof, PositionBound, TreeConfig,
+    VerificationBundle, VerifyResult, MAX_LEAVES, MAX_SUPPORTED_DEPTH, MMR, MMR_NODE, ROOT_TAG,
+    ROOT_TAGS, SPENT, TREE, TREE_DEPTH, VERSION, VERSION_ROOT,
+};
+use soroban_sdk::testutils::{Address as _, Events, Ledger};
+use soroban_sdk::{symbol_short, Address, Bytes, BytesN, Env, IntoVal, TryFromVal, Vec, U256, vec};
 use tiny_keccak::{Hasher, Keccak};
 
+mod reference;
+
 const ONE_EXPECTED_ROOT: [u8; 32] = [
     103, 232, 184, 92, 2, 115, 126, 8, 168, 169, 4, 142, 252, 141, 182, 26, 40, 44, 126, 173, 111,
     125, 121, 42, 113, 136, 235, 57, 9, 36, 47, 138,
@@ -30,6 +39,7 @@ fn one_element_works() {
     let leaf = BytesN::from_array(&env, &final_leaf);
 
     let tree = client.insert(&leaf);
+    assert_tree_invariants(&env, &client);
     let root = client.get_root();
     let array_root = root.to_array();
 
@@ -49,7 +59,7 @@ fn insert_fails_tree_full(){
     let contract_id = env.register_contract(None, Contract);
     let client = ContractClient::new(&env, &contract_id);
 
-    for i in 0..4294967296u64{
+    for i in 0..4294967297u64{
         let hash = MerkleTree::keccak256(vec![&env, [
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, i as u8,
@@ -96,6 +106,7 @@ fn three_elements_works() {
             128, 118, 74, 214, 62, 232, 51, 68, 49, 36, 148, 220, 41, 110,
         ],
     )); // hashed message of "three" https://docs.ethers.org/v5/api/utils/hashing/#utils-hashMessage
+    assert_tree_invariants(&env, &client);
 
     let tree = client.get_tree();
     let root = client.get_root();
@@ -347,8 +358,4786 @@ fn check_root_from_leaf() {
         ],
     );
 
-    let root = MerkleTree::branch_root(env.clone(), one, path, 0);
+    let root = MerkleTree::branch_root(env.clone(), one, path, leaf_index(0));
     let array_root = root.to_array();
 
     assert!(array_root.eq(&THREE_EXPECTED_ROOT), "Error in tree root");
 }
+
+#[test]
+fn position_bound_proof_fails_at_other_position() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            depth: 3,
+            ..TreeConfig::default()
+        },
+    );
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let branch = vec![
+        &env,
+        BytesN::from_array(&env, &[2; 32]),
+        BytesN::from_array(&env, &[3; 32]),
+        BytesN::from_array(&env, &[4; 32]),
+    ];
+
+    let root = MerkleTree::position_bound_branch_root(
+        env.clone(),
+        3,
+        leaf.clone(),
+        branch.clone(),
+        leaf_index(5),
+    );
+    let plain_root = MerkleTree::branch_root_with(
+        env.clone(),
+        HashAlgo::Keccak256,
+        3,
+        leaf.clone(),
+        branch.clone(),
+        leaf_index(5),
+    );
+
+    assert!(
+        root != plain_root,
+        "Error position-bound root equals plain root"
+    );
+    assert!(
+        client.verify_position_bound(&leaf, &branch, &leaf_index(5), &root),
+        "Error in position-bound verification"
+    );
+    assert!(
+        !client.verify_position_bound(&leaf, &branch, &leaf_index(4), &root),
+        "Error proof replayed at another position"
+    );
+    assert!(
+        !client.verify_position_bound(&leaf, &branch, &leaf_index(1), &root),
+        "Error proof replayed at another position"
+    );
+
+    // Missing siblings are not padded with zeros
+    let short = branch.slice(0..2);
+    assert!(
+        client.try_verify_position_bound(&leaf, &short, &leaf_index(5), &root)
+            == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+        "Error short position-bound branch accepted"
+    );
+}
+
+#[test]
+fn hash_ops_grows_per_insert() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    // Inserting leaf n hashes trailing_zeros(n) times: 0, 1, 0, 2, 0.
+    let expected: [u64; 5] = [0, 1, 1, 3, 3];
+    for (i, total) in expected.iter().enumerate() {
+        client.insert(&BytesN::from_array(&env, &[i as u8; 32]));
+        assert_tree_invariants(&env, &client);
+        assert!(client.hash_ops() == *total, "Error in hash op count");
+    }
+}
+
+/// Index argument as a client sends it, without the count check of
+/// `LeafIndex::new`, e.g. for an index past the last leaf.
+fn leaf_index(index: u64) -> LeafIndex {
+    LeafIndex(index)
+}
+
+/// Builds a full proof from the given low-level siblings, filling the
+/// remaining levels with the zero-hash tower.
+fn zero_padded_proof(env: &Env, siblings: Vec<BytesN<32>>) -> Vec<BytesN<32>> {
+    let mut proof = MerkleTree::zero_hashes(env.clone());
+    for (i, sibling) in siblings.iter().enumerate() {
+        proof.set(i as u32, sibling);
+    }
+    proof
+}
+
+#[test]
+fn verify_flexible_accepts_leaf_and_preimage() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let preimage = Bytes::from_slice(&env, b"a preimage longer than thirty-two bytes");
+    let mut hasher = Keccak::v256();
+    let mut output: [u8; 32] = [0; 32];
+    hasher.update(b"a preimage longer than thirty-two bytes");
+    hasher.finalize(&mut output);
+
+    let hashed_leaf = BytesN::from_array(&env, &output);
+    let plain_leaf = BytesN::from_array(&env, &[7; 32]);
+    client.insert(&hashed_leaf);
+    client.insert(&plain_leaf);
+
+    let proof_0 = zero_padded_proof(&env, vec![&env, plain_leaf.clone()]);
+    let proof_1 = zero_padded_proof(&env, vec![&env, hashed_leaf.clone()]);
+
+    assert!(
+        client.verify_flexible(&preimage, &proof_0, &leaf_index(0)),
+        "Error verifying preimage"
+    );
+    assert!(
+        client.verify_flexible(
+            &Bytes::from_array(&env, &plain_leaf.to_array()),
+            &proof_1,
+            &leaf_index(1)
+        ),
+        "Error verifying leaf"
+    );
+    assert!(
+        !client.verify_flexible(
+            &Bytes::from_array(&env, &hashed_leaf.to_array()),
+            &proof_1,
+            &leaf_index(1)
+        ),
+        "Error verifying wrong leaf"
+    );
+}
+
+#[test]
+fn storage_footprint_grows_with_frontier() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let mut footprint = client.storage_footprint();
+    // After 1, 2 and 4 leaves the frontier holds 1, 2 and 3 entries.
+    for count in [1u64, 2, 4] {
+        while client.get_tree().count < count {
+            client.insert(&BytesN::from_array(&env, &[count as u8; 32]));
+        }
+        let next = client.storage_footprint();
+        assert!(next > footprint, "Error footprint did not grow");
+        footprint = next;
+    }
+}
+
+/// Widens a proof to 64-byte siblings, appending `metadata` to every node.
+fn widen_proof(env: &Env, proof: Vec<BytesN<32>>, metadata: u8) -> Vec<BytesN<64>> {
+    let mut wide = vec![env];
+    for node in proof.iter() {
+        let mut bytes = [metadata; 64];
+        bytes[..32].copy_from_slice(&node.to_array());
+        wide.push_back(BytesN::from_array(env, &bytes));
+    }
+    wide
+}
+
+#[test]
+fn verify_wide_extracts_node_hashes() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let leaf_0 = BytesN::from_array(&env, &[1; 32]);
+    let leaf_1 = BytesN::from_array(&env, &[2; 32]);
+    client.insert(&leaf_0);
+    client.insert(&leaf_1);
+
+    let proof = zero_padded_proof(&env, vec![&env, leaf_1.clone()]);
+    let wide = widen_proof(&env, proof, 0xff);
+
+    assert!(
+        client.verify_wide(&leaf_0, &wide, &leaf_index(0)),
+        "Error verifying wide proof"
+    );
+    assert!(
+        !client.verify_wide(&leaf_1, &wide, &leaf_index(0)),
+        "Error verifying wrong leaf"
+    );
+}
+
+#[test]
+fn first_leaf_proof_tracks_root() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    assert!(
+        client.first_leaf_proof().is_none(),
+        "Error empty tree proof"
+    );
+
+    let first = BytesN::from_array(&env, &[1; 32]);
+    client.insert(&first);
+    for i in 2..12u8 {
+        let proof = client.first_leaf_proof().expect("Error to get proof");
+        let root = MerkleTree::branch_root(env.clone(), first.clone(), proof, leaf_index(0));
+        assert!(root == client.get_root(), "Error in first leaf proof");
+
+        client.insert(&BytesN::from_array(&env, &[i; 32]));
+        assert_tree_invariants(&env, &client);
+    }
+}
+
+#[test]
+fn next_insert_touches_matches_frontier_diff() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    for i in 0..9u8 {
+        let touched = client.next_insert_touches();
+        let ops = client.hash_ops();
+        let before = client.get_tree().branch;
+        let after = client
+            .insert(&BytesN::from_array(&env, &[i + 1; 32]))
+            .branch;
+        assert_tree_invariants(&env, &client);
+
+        // Carried levels go back to their zero hash, the last one is written
+        for level in 0..after.len() {
+            let changed = before.get(level) != after.get(level);
+            assert!(
+                changed == touched.contains(level),
+                "Error in touched levels"
+            );
+        }
+        assert!(
+            touched.len() as u64 - 1 == client.hash_ops() - ops,
+            "Error in touched levels count"
+        );
+    }
+}
+
+#[test]
+fn salted_leaves_differ_per_deployment() {
+    let env = Env::default();
+    let client_a = ContractClient::new(&env, &env.register_contract(None, Contract));
+    let client_b = ContractClient::new(&env, &env.register_contract(None, Contract));
+    client_a.initialize(&TreeConfig {
+        salt: Some(BytesN::from_array(&env, &[1; 32])),
+        ..TreeConfig::default()
+    });
+    client_b.initialize(&TreeConfig {
+        salt: Some(BytesN::from_array(&env, &[2; 32])),
+        ..TreeConfig::default()
+    });
+
+    let value = BytesN::from_array(&env, &[9; 32]);
+    let leaf_a = client_a.insert_salted(&value).branch.get_unchecked(0);
+    let leaf_b = client_b.insert_salted(&value).branch.get_unchecked(0);
+    assert!(leaf_a != leaf_b, "Error salted leaves are equal");
+    assert!(leaf_a != value, "Error value inserted unsalted");
+
+    let proof = MerkleTree::zero_hashes(env.clone());
+    assert!(
+        client_a.verify_salted(&value, &proof, &leaf_index(0)),
+        "Error verifying salted value"
+    );
+    assert!(
+        !client_a.verify_salted(&BytesN::from_array(&env, &[8; 32]), &proof, &leaf_index(0)),
+        "Error verifying wrong value"
+    );
+}
+
+#[test]
+fn initialize_twice_fails() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    client.initialize(&TreeConfig::default());
+    let result = client.try_initialize(&TreeConfig::default());
+    assert!(
+        result == Err(Ok(Error::AlreadyInitialized.into())),
+        "Error initialized twice"
+    );
+}
+
+#[test]
+fn get_config_returns_initialized_config() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    assert!(
+        client.get_config() == TreeConfig::default(),
+        "Error in default config"
+    );
+
+    let config = TreeConfig {
+        salt: Some(BytesN::from_array(&env, &[3; 32])),
+        ..TreeConfig::default()
+    };
+    client.initialize(&config);
+    assert!(client.get_config() == config, "Error in stored config");
+}
+
+#[test]
+fn insert_salted_without_salt_fails() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let result = client.try_insert_salted(&BytesN::from_array(&env, &[1; 32]));
+    assert!(
+        result == Err(Ok(Error::SaltNotSet.into())),
+        "Error inserted unsalted"
+    );
+}
+
+#[test]
+fn verify_adjacent_checks_consecutive_indices() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let leaves = [
+        BytesN::from_array(&env, &[1; 32]),
+        BytesN::from_array(&env, &[2; 32]),
+        BytesN::from_array(&env, &[3; 32]),
+    ];
+    for leaf in leaves.iter() {
+        client.insert(leaf);
+    }
+
+    let pair_01 = BytesN::from_array(
+        &env,
+        &MerkleTree::keccak256(vec![&env, leaves[0].to_array(), leaves[1].to_array()]),
+    );
+    let leaf_2_zero = BytesN::from_array(
+        &env,
+        &MerkleTree::keccak256(vec![&env, leaves[2].to_array(), [0; 32]]),
+    );
+    let proof_0 = zero_padded_proof(&env, vec![&env, leaves[1].clone(), leaf_2_zero.clone()]);
+    let proof_1 = zero_padded_proof(&env, vec![&env, leaves[0].clone(), leaf_2_zero]);
+    let proof_2 = zero_padded_proof(
+        &env,
+        vec![&env, BytesN::from_array(&env, &[0; 32]), pair_01],
+    );
+
+    assert!(
+        client.verify_adjacent(&leaves[0], &leaf_index(0), &proof_0, &leaves[1], &proof_1),
+        "Error verifying adjacent leaves"
+    );
+    assert!(
+        client.verify_adjacent(&leaves[1], &leaf_index(1), &proof_1, &leaves[2], &proof_2),
+        "Error verifying adjacent leaves"
+    );
+    assert!(
+        !client.verify_adjacent(&leaves[0], &leaf_index(0), &proof_0, &leaves[2], &proof_2),
+        "Error verifying non-adjacent leaves"
+    );
+    assert!(
+        !client.verify_adjacent(&leaves[1], &leaf_index(1), &proof_1, &leaves[0], &proof_0),
+        "Error verifying leaves in reverse order"
+    );
+}
+
+#[test]
+fn get_tree_fails_on_corrupted_state() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    env.as_contract(&contract_id, || {
+        env.storage().persistent().set(&TREE, &42u32);
+    });
+
+    let result = client.try_get_tree();
+    assert!(
+        result == Err(Ok(Error::StateCorrupted.into())),
+        "Error reading corrupted tree"
+    );
+}
+
+/// Registers a contract initialized with `config`.
+fn setup_with_config<'a>(env: &Env, config: TreeConfig) -> ContractClient<'a> {
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(env, &contract_id);
+    client.initialize(&config);
+    client
+}
+
+/// Registers a contract storing its leaves and inserts `count` leaves, the
+/// `i`-th leaf being `[i + 1; 32]`.
+fn setup_with_leaves<'a>(env: &Env, count: u8) -> ContractClient<'a> {
+    let client = setup_with_config(
+        env,
+        TreeConfig {
+            store_leaves: true,
+            ..TreeConfig::default()
+        },
+    );
+    for i in 0..count {
+        client.insert(&BytesN::from_array(env, &[i + 1; 32]));
+        assert_tree_invariants(env, &client);
+    }
+    client
+}
+
+/// Same as `setup_with_leaves` for a tree of depth `depth`.
+fn setup_at_depth<'a>(env: &Env, depth: u32, count: u8) -> ContractClient<'a> {
+    let client = setup_with_config(
+        env,
+        TreeConfig {
+            store_leaves: true,
+            depth,
+            ..TreeConfig::default()
+        },
+    );
+    for i in 0..count {
+        client.insert(&BytesN::from_array(env, &[i + 1; 32]));
+    }
+    client
+}
+
+/// Checks what must hold after any operation: the frontier spans exactly the
+/// levels `count` has reached, every level clear in `count` holds its zero
+/// hash, and, with stored leaves, every level set in `count` holds the root of its
+/// completed subtree and every stored leaf's proof verifies against the
+/// current root.
+fn assert_tree_invariants(env: &Env, client: &ContractClient) {
+    let tree = client.get_tree();
+    let levels = 64 - tree.count.leading_zeros();
+    // A full tree keeps its root above the top level
+    assert!(
+        tree.branch.len() <= tree.depth() + 1,
+        "Error frontier deeper than tree"
+    );
+    assert!(tree.branch.len() == levels, "Error in frontier length");
+    let zeroes = MerkleTree::zero_hashes_at(env.clone(), tree.hash_algo(), tree.depth());
+    for level in 0..u32::min(levels, tree.depth()) {
+        if (tree.count >> level) & 1 == 0 {
+            assert!(
+                tree.branch.get_unchecked(level) == zeroes.get_unchecked(level),
+                "Error in cleared frontier level"
+            );
+        }
+    }
+    if !client.get_config().store_leaves {
+        return;
+    }
+
+    // Levels set in `count` hold the root of their completed subtree
+    let algo = tree.hash_algo();
+    for level in 0..levels {
+        if (tree.count >> level) & 1 == 1 {
+            let end = ((tree.count >> level) << level) as u32;
+            let start = end - (1 << level);
+            let mut nodes: std::vec::Vec<BytesN<32>> = (start..end)
+                .map(|i| algo.hash_leaf(env, &client.get_leaf(&i).unwrap()))
+                .collect();
+            while nodes.len() > 1 {
+                nodes = nodes
+                    .chunks(2)
+                    .map(|pair| algo.hash_pair(env, &pair[0], &pair[1]))
+                    .collect();
+            }
+            assert!(
+                nodes[0] == tree.branch.get_unchecked(level),
+                "Error in frontier node"
+            );
+        }
+    }
+
+    let root = client.get_root();
+    for index in 0..tree.count as u32 {
+        let proof = client.portable_proof(&index);
+        let computed = MerkleTree::branch_root_with(
+            env.clone(),
+            tree.hash_algo(),
+            tree.depth(),
+            proof.leaf,
+            proof.branch,
+            proof.index,
+        );
+        assert!(computed == root, "Error in stored leaf proof");
+    }
+}
+
+#[test]
+fn window_root_matches_independent_computation() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+
+    let hash = |a: [u8; 32], b: [u8; 32]| {
+        let mut hasher = Keccak::v256();
+        let mut output: [u8; 32] = [0; 32];
+        hasher.update(&a);
+        hasher.update(&b);
+        hasher.finalize(&mut output);
+        output
+    };
+    // Leaves 1..4 are [2; 32], [3; 32], [4; 32], padded with one zero leaf.
+    let expected = hash(hash([2; 32], [3; 32]), hash([4; 32], [0; 32]));
+
+    assert!(
+        client.window_root(&1, &4).to_array() == expected,
+        "Error in window root"
+    );
+    assert!(
+        client.window_root(&4, &5).to_array() == [5; 32],
+        "Error in single leaf window root"
+    );
+    assert!(
+        client.try_window_root(&3, &6) == Err(Ok(Error::InvalidRange.into())),
+        "Error window past count"
+    );
+}
+
+#[test]
+fn window_root_requires_stored_leaves() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+
+    assert!(
+        client.try_window_root(&0, &1) == Err(Ok(Error::LeavesNotStored.into())),
+        "Error window root without stored leaves"
+    );
+}
+
+#[test]
+fn permissioned_insert_requires_allowlisted_inserter() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::random(&env);
+    let inserter = Address::random(&env);
+    let outsider = Address::random(&env);
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            admin: Some(admin),
+            permissioned: true,
+            ..TreeConfig::default()
+        },
+    );
+    client.add_inserter(&inserter);
+
+    let node = BytesN::from_array(&env, &[1; 32]);
+    assert!(
+        client.insert_from(&inserter, &node).count == 1,
+        "Error in authorized insert"
+    );
+    assert!(
+        client.try_insert_from(&outsider, &node) == Err(Ok(Error::Unauthorized.into())),
+        "Error in unauthorized insert"
+    );
+    assert!(
+        client.try_insert(&node) == Err(Ok(Error::Unauthorized.into())),
+        "Error in anonymous insert"
+    );
+
+    client.remove_inserter(&inserter);
+    assert!(
+        client.try_insert_from(&inserter, &node) == Err(Ok(Error::Unauthorized.into())),
+        "Error in removed inserter insert"
+    );
+}
+
+#[test]
+fn permissioned_config_requires_admin() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let result = client.try_initialize(&TreeConfig {
+        permissioned: true,
+        ..TreeConfig::default()
+    });
+    assert!(
+        result == Err(Ok(Error::AdminNotSet.into())),
+        "Error permissioned tree without admin"
+    );
+}
+
+#[test]
+fn verify_with_frontier_checks_old_state() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let leaf_0 = BytesN::from_array(&env, &[1; 32]);
+    let leaf_1 = BytesN::from_array(&env, &[2; 32]);
+    client.insert(&leaf_0);
+    let old = client.insert(&leaf_1);
+    client.insert(&BytesN::from_array(&env, &[3; 32]));
+
+    let old_proof = zero_padded_proof(&env, vec![&env, leaf_1.clone()]);
+    assert!(
+        client.verify_with_frontier(&old.branch, &old.count, &leaf_0, &old_proof, &leaf_index(0)),
+        "Error verifying against old frontier"
+    );
+    assert!(
+        !client.verify_flexible(
+            &Bytes::from_array(&env, &leaf_0.to_array()),
+            &old_proof,
+            &leaf_index(0)
+        ),
+        "Error old proof verified against current root"
+    );
+    assert!(
+        !client.verify_with_frontier(&old.branch, &old.count, &leaf_1, &old_proof, &leaf_index(0)),
+        "Error verifying wrong leaf against old frontier"
+    );
+}
+
+#[test]
+fn insert_hashed_inserts_preimage_hashes() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 2);
+
+    let preimages = vec![
+        &env,
+        Bytes::from_slice(&env, b"one"),
+        Bytes::from_slice(&env, b"two"),
+        Bytes::from_slice(&env, b"three"),
+    ];
+    let indices = client.insert_hashed(&preimages);
+    assert_tree_invariants(&env, &client);
+    assert!(indices == vec![&env, 2, 3, 4], "Error in inserted indices");
+
+    for (index, preimage) in indices.iter().zip(preimages.iter()) {
+        let mut hasher = Keccak::v256();
+        let mut output: [u8; 32] = [0; 32];
+        let mut buffer = [0; 8];
+        let size = preimage.len() as usize;
+        preimage.copy_into_slice(&mut buffer[..size]);
+        hasher.update(&buffer[..size]);
+        hasher.finalize(&mut output);
+
+        assert!(
+            client.window_root(&index, &(index + 1)).to_array() == output,
+            "Error in hashed leaf"
+        );
+    }
+}
+
+#[test]
+fn verify_diagnostic_reports_divergence() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+
+    let first = BytesN::from_array(&env, &[1; 32]);
+    let third = BytesN::from_array(&env, &[3; 32]);
+    let third_zero = BytesN::from_array(
+        &env,
+        &MerkleTree::keccak256(vec![&env, third.to_array(), [0; 32]]),
+    );
+    let proof = zero_padded_proof(
+        &env,
+        vec![&env, BytesN::from_array(&env, &[2; 32]), third_zero],
+    );
+
+    let result = client.verify_diagnostic(&first, &proof, &leaf_index(0));
+    assert!(
+        result
+            == VerifyResult {
+                valid: true,
+                computed_root: client.get_root(),
+                expected_root: client.get_root(),
+                divergence_level: None,
+            },
+        "Error in passing diagnostic"
+    );
+
+    // A wrong level 1 sibling makes the path diverge from level 2 upwards.
+    let mut bad_proof = proof.clone();
+    bad_proof.set(1, BytesN::from_array(&env, &[9; 32]));
+    let result = client.verify_diagnostic(&first, &bad_proof, &leaf_index(0));
+    assert!(!result.valid, "Error in failing diagnostic");
+    assert!(
+        result.computed_root != result.expected_root,
+        "Error in roots"
+    );
+    assert!(
+        result.divergence_level == Some(2),
+        "Error in divergence level"
+    );
+
+    let result = client.verify_diagnostic(&third, &proof, &leaf_index(0));
+    assert!(
+        result.divergence_level == Some(0),
+        "Error in leaf divergence"
+    );
+}
+
+#[test]
+fn is_empty_root_detects_untouched_tree() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let empty_root = client.get_root();
+    assert!(empty_root.to_array() != [0; 32], "Error empty root is zero");
+    assert!(client.is_empty_root(&empty_root), "Error in empty root");
+
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(
+        !client.is_empty_root(&client.get_root()),
+        "Error in populated root"
+    );
+}
+
+#[test]
+fn verify_checked_requires_expected_algorithm() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    client.insert(&leaf);
+    let proof = MerkleTree::zero_hashes(env.clone());
+
+    assert!(
+        client.verify_checked(&leaf, &proof, &leaf_index(0), &HashAlgo::Keccak256),
+        "Error verifying with matching algorithm"
+    );
+    assert!(
+        !client.verify_checked(&leaf, &proof, &leaf_index(0), &HashAlgo::Sha256),
+        "Error verifying with mismatching algorithm"
+    );
+}
+
+#[test]
+fn initialize_rejects_unsupported_algorithm() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    // Only the binary tree follows `hash_algo`, the k-ary tree is Keccak256
+    let result = client.try_initialize(&TreeConfig {
+        hash_algo: HashAlgo::Sha256,
+        fanout: 4,
+        ..TreeConfig::default()
+    });
+    assert!(
+        result == Err(Ok(Error::UnsupportedHashAlgo.into())),
+        "Error initialized with unsupported algorithm"
+    );
+}
+
+#[test]
+fn contains_all_requires_every_proof() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let leaf_0 = BytesN::from_array(&env, &[1; 32]);
+    let leaf_1 = BytesN::from_array(&env, &[2; 32]);
+    client.insert(&leaf_0);
+    client.insert(&leaf_1);
+
+    let proof_0 = MerkleProof {
+        leaf: leaf_0.clone(),
+        branch: zero_padded_proof(&env, vec![&env, leaf_1.clone()]),
+        index: LeafIndex(0),
+    };
+    let proof_1 = MerkleProof {
+        leaf: leaf_1.clone(),
+        branch: zero_padded_proof(&env, vec![&env, leaf_0.clone()]),
+        index: LeafIndex(1),
+    };
+    let invalid = MerkleProof {
+        index: LeafIndex(1),
+        ..proof_0.clone()
+    };
+
+    assert!(
+        client.contains_all(&vec![&env, proof_0.clone(), proof_1.clone()]),
+        "Error with all proofs valid"
+    );
+    assert!(
+        !client.contains_all(&vec![&env, proof_0, invalid, proof_1]),
+        "Error with one invalid proof"
+    );
+}
+
+#[test]
+fn bound_commitment_only_verifies_for_its_sender() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    let sender = Address::random(&env);
+    let other = Address::random(&env);
+    let node = BytesN::from_array(&env, &[5; 32]);
+    let leaf = client.insert_bound(&sender, &node).branch.get_unchecked(0);
+    assert!(leaf != node, "Error node inserted unbound");
+
+    let proof = MerkleTree::zero_hashes(env.clone());
+    assert!(
+        client.verify_bound(&sender, &node, &proof, &leaf_index(0)),
+        "Error verifying bound commitment"
+    );
+    assert!(
+        !client.verify_bound(&other, &node, &proof, &leaf_index(0)),
+        "Error verifying commitment for another sender"
+    );
+}
+
+#[test]
+fn portable_proof_is_self_consistent() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 6);
+
+    for index in 0..6u32 {
+        let proof: PortableProof = client.portable_proof(&index);
+        let root =
+            MerkleTree::branch_root(env.clone(), proof.leaf.clone(), proof.branch, proof.index);
+
+        assert!(
+            proof.leaf.to_array() == [index as u8 + 1; 32],
+            "Error in leaf"
+        );
+        assert!(root == proof.root, "Error in proof root");
+        assert!(proof.root == client.get_root(), "Error in current root");
+    }
+    assert!(
+        client.try_portable_proof(&6) == Err(Ok(Error::InvalidRange.into())),
+        "Error proof past count"
+    );
+}
+
+/// Txid or root as shown by explorers, reversed into internal byte order.
+fn bitcoin_hash(env: &Env, display: &str) -> BytesN<32> {
+    let mut _bytes = [0u8; 32];
+    for i in 0..32 {
+        _bytes[31 - i] = u8::from_str_radix(&display[2 * i..2 * i + 2], 16).unwrap();
+    }
+    return BytesN::from_array(env, &_bytes);
+}
+
+#[test]
+fn bitcoin_root_matches_block_100000() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+
+    let txids = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ];
+    let mut leaves = vec![&env];
+    for txid in txids {
+        leaves.push_back(bitcoin_hash(&env, txid));
+    }
+    let expected = bitcoin_hash(
+        &env,
+        "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766",
+    );
+    assert!(
+        client.bitcoin_root(&leaves) == expected,
+        "Error in block root"
+    );
+
+    // An odd level duplicates its last node instead of padding with zeroes.
+    leaves.pop_back();
+    let odd = client.bitcoin_root(&leaves);
+    let mut duplicated = leaves.clone();
+    duplicated.push_back(leaves.get_unchecked(2));
+    assert!(
+        odd == client.bitcoin_root(&duplicated),
+        "Error in odd level"
+    );
+    assert!(
+        odd == bitcoin_hash(
+            &env,
+            "fa435470825de273081dcc706b25514c936fa6dc80ab965ce6970d68ddd0b553"
+        ),
+        "Error in odd root"
+    );
+
+    let single = vec![&env, leaves.get_unchecked(0)];
+    assert!(
+        client.bitcoin_root(&single) == leaves.get_unchecked(0),
+        "Error in single leaf"
+    );
+}
+
+#[test]
+fn batch_cost_matches_measured_hash_ops() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+
+    let mut inserted = 0;
+    for n in [1u32, 3, 4, 9, 15] {
+        let predicted = client.batch_cost(&n);
+        let before = client.hash_ops();
+
+        let mut preimages = vec![&env];
+        for i in 0..n {
+            preimages.push_back(Bytes::from_array(&env, &(inserted + i).to_be_bytes()));
+        }
+        client.insert_hashed(&preimages);
+        assert_tree_invariants(&env, &client);
+        inserted += n;
+
+        let measured = client.hash_ops() - before;
+        assert!(predicted as u64 == measured, "Error in batch cost");
+    }
+    assert!(client.batch_cost(&0) == 0, "Error in empty batch");
+}
+
+#[test]
+fn root_with_ctx_accepts_genuine_zeroes() {
+    let env = Env::default();
+    let tree = MerkleTree::new(&env);
+
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+    assert!(
+        tree.root_with_ctx(env.clone(), &zeroes.get_unchecked(0), zeroes.clone())
+            == MerkleTree::empty_root(env.clone()),
+        "Error in genuine zeroes"
+    );
+}
+
+#[test]
+#[should_panic]
+fn check_zero_hashes_rejects_tampered_zeroes() {
+    let env = Env::default();
+
+    // Only entry 17 is replaced, everything below it is still genuine.
+    let mut zeroes = MerkleTree::zero_hashes(env.clone());
+    zeroes.set(17, BytesN::from_array(&env, &[1; 32]));
+    MerkleTree::check_zero_hashes(&env, HashAlgo::Keccak256, &zeroes.get_unchecked(0), &zeroes);
+}
+
+#[test]
+fn leaf_index_round_trips() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+
+    let index = LeafIndex::new(2, 3).expect("Error in index of count");
+    assert!(u64::from(index) == 2, "Error in conversion");
+    assert!(LeafIndex::new(3, 3).is_none(), "Error index past count");
+    assert!(LeafIndex::new(0, 0).is_none(), "Error index of empty");
+    assert!(LeafIndex::last(3) == Some(index), "Error in last of count");
+    assert!(LeafIndex::last(0).is_none(), "Error in last of empty");
+    assert!(
+        LeafIndex::last(client.get_count()) == Some(index),
+        "Error in last of tree count"
+    );
+    assert!(
+        LeafIndex::last(MAX_LEAVES) == LeafIndex::new(u32::MAX as u64, MAX_LEAVES),
+        "Error in last of full tree"
+    );
+
+    // Accepted by the proof APIs and the contract
+    let proof = client.portable_proof(&2);
+    assert!(proof.index == index, "Error in proof index");
+    let root =
+        MerkleTree::branch_root(env.clone(), proof.leaf.clone(), proof.branch.clone(), index);
+    assert!(root == proof.root, "Error in typed index");
+    assert!(
+        client.verify(&proof.leaf, &proof.branch, &index),
+        "Error in verify with typed index"
+    );
+
+    let bundle = MerkleProof {
+        leaf: proof.leaf,
+        branch: proof.branch,
+        index,
+    };
+    assert!(
+        client.contains_all(&vec![&env, bundle]),
+        "Error in proof with typed index"
+    );
+}
+
+#[test]
+fn leafset_commitment_matches_stored_leaves() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+
+    let commitment = |leaves: &[u8]| {
+        let mut hasher = Keccak::v256();
+        let mut output: [u8; 32] = [0; 32];
+        for leaf in leaves {
+            hasher.update(&[*leaf; 32]);
+        }
+        hasher.finalize(&mut output);
+        BytesN::from_array(&env, &output)
+    };
+
+    assert!(
+        client.verify_leafset_commitment(&commitment(&[1, 2, 3])),
+        "Error in commitment"
+    );
+    assert!(
+        !client.verify_leafset_commitment(&commitment(&[1, 3, 2])),
+        "Error order ignored"
+    );
+    assert!(
+        !client.verify_leafset_commitment(&commitment(&[1, 2])),
+        "Error leaf missing"
+    );
+    assert!(
+        !client.verify_leafset_commitment(&client.get_root()),
+        "Error root accepted"
+    );
+}
+
+#[test]
+fn insert_event_chains_roots() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+
+    let mut previous = client.get_root();
+    for i in 0..3u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        client.insert(&leaf);
+
+        let (_, topics, data) = env.events().all().last().unwrap();
+        let event = InsertEvent::try_from_val(&env, &data).unwrap();
+        assert!(
+            topics == (symbol_short!("insert"),).into_val(&env),
+            "Error in event topics"
+        );
+        assert!(event.previous_root == previous, "Error in previous root");
+        assert!(event.root == client.get_root(), "Error in new root");
+        assert!(
+            event.index == i as u32 && event.leaf == leaf,
+            "Error in inserted leaf"
+        );
+        previous = event.root;
+        assert_tree_invariants(&env, &client);
+    }
+}
+
+#[test]
+fn empty_suffix_root_is_aligned_zero_subtree() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+
+    let zero_subtree = |height: u32| {
+        let mut node = [0u8; 32];
+        for _ in 0..height {
+            node = MerkleTree::keccak256(vec![&env, node, node]);
+        }
+        BytesN::from_array(&env, &node)
+    };
+
+    assert!(
+        client.empty_suffix_root() == zero_subtree(32),
+        "Error in count 0"
+    );
+    assert!(
+        client.empty_suffix_root() == MerkleTree::empty_root(env.clone()),
+        "Error in empty root"
+    );
+
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(
+        client.empty_suffix_root() == zero_subtree(0),
+        "Error in count 1"
+    );
+
+    // Leaves 6 and 7 form the aligned empty pair after count 6.
+    for i in 2..=6u8 {
+        client.insert(&BytesN::from_array(&env, &[i; 32]));
+    }
+    assert!(
+        client.empty_suffix_root() == zero_subtree(1),
+        "Error in count 6"
+    );
+}
+
+#[test]
+fn verify_at_version_tracks_updates() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            admin: Some(Address::random(&env)),
+            mutable: true,
+            ..TreeConfig::default()
+        },
+    );
+    for i in 0..3u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+
+    let old = BytesN::from_array(&env, &[2; 32]);
+    let new = BytesN::from_array(&env, &[9; 32]);
+    let first = client.update_leaf(&1, &new);
+    assert_tree_invariants(&env, &client);
+    let proof_1 = client.portable_proof(&1);
+    assert!(proof_1.leaf == new, "Error in updated leaf");
+
+    let second = client.update_leaf(&1, &old);
+    assert_tree_invariants(&env, &client);
+    let proof_2 = client.portable_proof(&1);
+    assert!(first == 1 && second == 2, "Error in versions");
+
+    let verify = |leaf: &BytesN<32>, proof: &PortableProof, version: u32| {
+        client.verify_at_version(leaf, &proof.branch, &leaf_index(1), &version)
+    };
+    assert!(verify(&new, &proof_1, 1), "Error in version 1 membership");
+    assert!(!verify(&old, &proof_1, 1), "Error old leaf in version 1");
+    assert!(verify(&old, &proof_2, 2), "Error in version 2 membership");
+    assert!(!verify(&new, &proof_2, 2), "Error new leaf in version 2");
+    assert!(!verify(&old, &proof_2, 3), "Error in unknown version");
+
+    // The rebuilt frontier keeps inserting like the original one.
+    let reference = setup_with_leaves(&env, 4);
+    client.insert(&BytesN::from_array(&env, &[4; 32]));
+    assert!(
+        client.get_root() == reference.get_root(),
+        "Error in rebuilt frontier"
+    );
+    assert!(
+        client.first_leaf_proof() == reference.first_leaf_proof(),
+        "Error in rebuilt first path"
+    );
+}
+
+#[test]
+fn update_leaf_requires_mutable_tree() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Some(Address::random(&env));
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            admin: admin.clone(),
+            ..TreeConfig::default()
+        },
+    );
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    assert!(
+        client.try_update_leaf(&0, &leaf) == Err(Ok(Error::TreeNotMutable.into())),
+        "Error update on immutable tree"
+    );
+
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    let config = TreeConfig {
+        admin,
+        mutable: true,
+        ..TreeConfig::default()
+    };
+    assert!(
+        client.try_initialize(&config) == Err(Ok(Error::LeavesNotStored.into())),
+        "Error mutable tree without leaves"
+    );
+}
+
+#[test]
+fn compact_drops_stale_frontier_entries() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    client.initialize(&TreeConfig {
+        admin: Some(Address::random(&env)),
+        ..TreeConfig::default()
+    });
+    for i in 0..5u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+    let tree = client.get_tree();
+    let root = client.get_root();
+
+    // Five leaves fill at most level 2, anything above it is stale.
+    let mut bloated = tree.clone();
+    for _ in 0..4 {
+        bloated.branch.push_back(BytesN::from_array(&env, &[7; 32]));
+    }
+    env.as_contract(&contract_id, || {
+        env.storage().persistent().set(&TREE, &bloated);
+    });
+
+    let compacted = client.compact();
+    assert_tree_invariants(&env, &client);
+    assert!(compacted.branch.len() == 3, "Error in frontier length");
+    assert!(compacted == tree, "Error in frontier contents");
+    assert!(
+        client.get_tree() == tree && client.get_root() == root,
+        "Error in stored tree"
+    );
+
+    let mut empty = MerkleTree {
+        branch: vec![&env, BytesN::from_array(&env, &[7; 32])],
+        count: 0,
+        depth: 32,
+        hash_algo: HashAlgo::Keccak256,
+    };
+    empty.compact(env.clone());
+    assert!(empty.branch.is_empty(), "Error in empty frontier");
+}
+
+#[test]
+fn verify_extension_replays_appends() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    for i in 0..3u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+    let old = client.get_tree();
+
+    let mut appended = vec![&env];
+    for i in 3..7u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        client.insert(&leaf);
+        appended.push_back(leaf);
+    }
+    let new_root = client.get_root();
+
+    let verify = |frontier: &Vec<BytesN<32>>, count: u64, appended: &Vec<BytesN<32>>| {
+        client.verify_extension(frontier, &count, appended, &new_root)
+    };
+    assert!(
+        verify(&old.branch, old.count, &appended),
+        "Error in genuine extension"
+    );
+
+    let mut tampered = appended.clone();
+    tampered.set(2, BytesN::from_array(&env, &[42; 32]));
+    assert!(
+        !verify(&old.branch, old.count, &tampered),
+        "Error tampered leaf accepted"
+    );
+    assert!(
+        !verify(&old.branch, old.count + 1, &appended),
+        "Error wrong count accepted"
+    );
+    assert!(
+        !verify(&vec![&env], old.count, &appended),
+        "Error short frontier accepted"
+    );
+}
+
+#[test]
+fn max_finalized_index_follows_complete_subtree() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 0);
+    assert!(client.max_finalized_index().is_none(), "Error in count 0");
+
+    let expected = [
+        None,
+        Some(1),
+        Some(1),
+        Some(3),
+        Some(3),
+        Some(3),
+        Some(3),
+        Some(7),
+        Some(7),
+    ];
+    for (i, boundary) in expected.iter().enumerate() {
+        client.insert(&BytesN::from_array(&env, &[i as u8 + 1; 32]));
+        assert!(
+            client.max_finalized_index() == *boundary,
+            "Error in boundary"
+        );
+    }
+
+    // The levels below the boundary subtree height stay put from here on.
+    let proof = client.portable_proof(&5);
+    for i in 9..16u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+    let later = client.portable_proof(&5);
+    for level in 0..3 {
+        assert!(
+            proof.branch.get(level) == later.branch.get(level),
+            "Error finalized level changed"
+        );
+    }
+    assert!(
+        proof.branch.get(3) != later.branch.get(3),
+        "Error in open level"
+    );
+}
+
+#[test]
+fn verify_and_nullify_rejects_reuse() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let config = TreeConfig {
+        admin: Some(Address::random(&env)),
+        store_leaves: true,
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config);
+    for i in 1..=3u8 {
+        client.insert(&BytesN::from_array(&env, &[i; 32]));
+    }
+    let proof = client.portable_proof(&1);
+    let nullifier = client.nullifier(&proof.leaf, &1);
+    assert!(
+        client.try_verify_and_nullify(&proof.leaf, &proof.branch, &leaf_index(1), &nullifier)
+            == Err(Ok(Error::RootNotFrozen.into())),
+        "Error claim without a frozen root"
+    );
+
+    client.freeze_root();
+    // Inserts after the freeze don't move the root claims are checked against
+    client.insert(&BytesN::from_array(&env, &[4; 32]));
+    client.verify_and_nullify(&proof.leaf, &proof.branch, &leaf_index(1), &nullifier);
+    assert!(
+        client.is_nullified(&nullifier),
+        "Error nullifier not recorded"
+    );
+
+    let reuse =
+        client.try_verify_and_nullify(&proof.leaf, &proof.branch, &leaf_index(1), &nullifier);
+    assert!(
+        reuse == Err(Ok(Error::NullifierUsed.into())),
+        "Error double spend accepted"
+    );
+
+    // A fresh nullifier doesn't make the same leaf claimable again
+    let fresh = BytesN::from_array(&env, &[0xaa; 32]);
+    let again = client.try_verify_and_nullify(&proof.leaf, &proof.branch, &leaf_index(1), &fresh);
+    assert!(
+        again == Err(Ok(Error::InvalidNullifier.into())),
+        "Error double spend with a fresh nullifier"
+    );
+
+    let other = client.nullifier(&proof.leaf, &2);
+    let invalid = client.try_verify_and_nullify(&proof.leaf, &proof.branch, &leaf_index(2), &other);
+    assert!(
+        invalid == Err(Ok(Error::InvalidProof.into())),
+        "Error invalid proof accepted"
+    );
+    assert!(
+        !client.is_nullified(&other),
+        "Error nullifier spent by invalid proof"
+    );
+
+    // The leaf inserted after the freeze isn't under the frozen root yet
+    let late = client.portable_proof(&3);
+    let late_nullifier = client.nullifier(&late.leaf, &3);
+    assert!(
+        client.try_verify_and_nullify(&late.leaf, &late.branch, &leaf_index(3), &late_nullifier)
+            == Err(Ok(Error::InvalidProof.into())),
+        "Error leaf past the frozen root accepted"
+    );
+}
+
+#[test]
+fn domain_bound_root_depends_on_chain() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 2);
+    let tag = BytesN::from_array(&env, &[0x11; 32]);
+
+    let mut chain = [0u8; 32];
+    chain[31] = 1;
+    let expected = MerkleTree::keccak256(vec![
+        &env,
+        chain,
+        tag.to_array(),
+        client.get_root().to_array(),
+    ]);
+    assert!(
+        client.domain_bound_root(&1, &tag).to_array() == expected,
+        "Error in encoding"
+    );
+
+    let other_chain = client.domain_bound_root(&2, &tag);
+    assert!(
+        client.domain_bound_root(&1, &tag) != other_chain,
+        "Error chain ignored"
+    );
+    let other_tag = client.domain_bound_root(&1, &BytesN::from_array(&env, &[0x22; 32]));
+    assert!(
+        client.domain_bound_root(&1, &tag) != other_tag,
+        "Error tag ignored"
+    );
+}
+
+/// Independent 4-ary root and proof, hashing the leaf levels directly and
+/// padding every level with its zero subtree.
+fn quaternary_proof(
+    env: &Env,
+    leaves: &Vec<BytesN<32>>,
+    index: u32,
+) -> (BytesN<32>, Vec<Vec<BytesN<32>>>) {
+    let hash = |children: Vec<BytesN<32>>| {
+        let mut hasher = Keccak::v256();
+        let mut output: [u8; 32] = [0; 32];
+        for child in children.iter() {
+            hasher.update(&child.to_array());
+        }
+        hasher.finalize(&mut output);
+        BytesN::from_array(env, &output)
+    };
+
+    let mut level = leaves.clone();
+    let mut zero = BytesN::from_array(env, &[0; 32]);
+    let mut position = index;
+    let mut proof = vec![env];
+    for _ in 0..16 {
+        while level.is_empty() || level.len() % 4 != 0 {
+            level.push_back(zero.clone());
+        }
+        let mut siblings = vec![env];
+        for i in position / 4 * 4..position / 4 * 4 + 4 {
+            if i != position {
+                siblings.push_back(level.get_unchecked(i));
+            }
+        }
+        proof.push_back(siblings);
+
+        let mut next = vec![env];
+        for i in (0..level.len()).step_by(4) {
+            next.push_back(hash(level.slice(i..i + 4)));
+        }
+        level = next;
+        zero = hash(vec![env, zero.clone(), zero.clone(), zero.clone(), zero]);
+        position /= 4;
+    }
+    (level.get_unchecked(0), proof)
+}
+
+#[test]
+fn quaternary_tree_matches_independent_implementation() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            fanout: 4,
+            ..TreeConfig::default()
+        },
+    );
+    assert!(KaryTree::kary_depth(4) == 16, "Error in depth");
+
+    let mut leaves = vec![&env];
+    let empty = quaternary_proof(&env, &leaves, 0).0;
+    assert!(client.get_kary_root() == empty, "Error in empty root");
+
+    for i in 0..21u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        client.insert(&leaf);
+        leaves.push_back(leaf);
+
+        let (root, _) = quaternary_proof(&env, &leaves, 0);
+        assert!(client.get_kary_root() == root, "Error in 4-ary root");
+    }
+
+    for index in [0u32, 5, 16, 20] {
+        let (_, proof) = quaternary_proof(&env, &leaves, index);
+        let item = leaves.get_unchecked(index);
+        let index = index as u64;
+        assert!(
+            client.verify_kary(&item, &proof, &leaf_index(index)),
+            "Error in 4-ary proof"
+        );
+        assert!(
+            !client.verify_kary(&item, &proof, &leaf_index(index + 1)),
+            "Error in wrong index"
+        );
+    }
+
+    // 16 levels of 3 siblings against 32 levels of 1 for the binary tree.
+    let (_, proof) = quaternary_proof(&env, &leaves, 0);
+    assert!(
+        proof.len() == 16 && proof.get_unchecked(0).len() == 3,
+        "Error in proof shape"
+    );
+    assert!(client.get_tree().count == 21, "Error in binary tree");
+}
+#[test]
+fn fanout_must_be_supported_power_of_two() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    for fanout in [0u32, 3, 32] {
+        let config = TreeConfig {
+            fanout,
+            ..TreeConfig::default()
+        };
+        assert!(
+            client.try_initialize(&config) == Err(Ok(Error::InvalidFanout.into())),
+            "Error unsupported fanout accepted"
+        );
+    }
+    assert!(
+        client.try_get_kary_root() == Err(Ok(Error::InvalidFanout.into())),
+        "Error binary tree has a 4-ary root"
+    );
+}
+
+#[test]
+fn verify_top_down_accepts_reversed_proof() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+    let proof = client.portable_proof(&3);
+
+    let mut top_down = vec![&env];
+    for sibling in proof.branch.iter() {
+        top_down.push_front(sibling);
+    }
+    assert!(
+        client.verify_top_down(&proof.leaf, &top_down, &leaf_index(3)),
+        "Error in top-down proof"
+    );
+    let leaf_up = MerkleTree::branch_root(
+        env.clone(),
+        proof.leaf.clone(),
+        proof.branch,
+        leaf_index(3u64),
+    );
+    assert!(leaf_up == client.get_root(), "Error in leaf-up proof");
+
+    assert!(
+        !client.verify_top_down(&proof.leaf, &top_down, &leaf_index(2)),
+        "Error in wrong index"
+    );
+    top_down.pop_front();
+    assert!(
+        client.try_verify_top_down(&proof.leaf, &top_down, &leaf_index(3))
+            == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+        "Error in short proof"
+    );
+}
+
+#[test]
+fn final_root_inputs_hash_to_root() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    let hash_pair = |(left, right): (BytesN<32>, BytesN<32>)| {
+        let value = MerkleTree::keccak256(vec![&env, left.to_array(), right.to_array()]);
+        BytesN::from_array(&env, &value)
+    };
+
+    for i in 0..4u8 {
+        assert!(
+            hash_pair(client.final_root_inputs()) == client.get_root(),
+            "Error in inputs"
+        );
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+    assert!(
+        client.final_root_inputs().1 == zeroes.get_unchecked(31),
+        "Error in empty right half"
+    );
+
+    // Past 2^31 leaves the left half is complete and kept in the frontier.
+    let mut branch = vec![&env];
+    for level in 0..32u8 {
+        branch.push_back(BytesN::from_array(&env, &[level + 1; 32]));
+    }
+    let tree = MerkleTree {
+        branch: branch.clone(),
+        count: (1 << 31) + 3,
+        depth: 32,
+        hash_algo: HashAlgo::Keccak256,
+    };
+    env.as_contract(&contract_id, || {
+        env.storage().persistent().set(&TREE, &tree);
+    });
+    let inputs = client.final_root_inputs();
+    assert!(
+        inputs.0 == branch.get_unchecked(31),
+        "Error in full left half"
+    );
+    assert!(
+        hash_pair(inputs) == client.get_root(),
+        "Error in inputs past half"
+    );
+}
+
+#[test]
+fn insert_val_rejects_wrong_length() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    client.insert_val(&leaf.clone().into_val(&env));
+    let expected = setup_with_config(&env, TreeConfig::default());
+    expected.insert(&leaf);
+    assert!(
+        client.get_root() == expected.get_root(),
+        "Error in inserted value"
+    );
+
+    let short = Bytes::from_array(&env, &[1; 31]).into_val(&env);
+    let number = 7u32.into_val(&env);
+    for node in [short, number] {
+        assert!(
+            client.try_insert_val(&node) == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+            "Error wrong length accepted"
+        );
+    }
+    assert!(
+        client.get_tree().count == 1,
+        "Error rejected value inserted"
+    );
+}
+
+#[test]
+fn mmr_proves_membership_at_every_size() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    let hash = |left: &BytesN<32>, right: &BytesN<32>| {
+        let value = MerkleTree::keccak256(vec![&env, left.to_array(), right.to_array()]);
+        BytesN::from_array(&env, &value)
+    };
+    assert!(
+        client.mmr_root() == BytesN::from_array(&env, &[0; 32]),
+        "Error in empty root"
+    );
+
+    let mut leaves = vec![&env];
+    for i in 0..11u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        assert!(
+            client.mmr_append(&leaf) == i as u64,
+            "Error in appended index"
+        );
+        leaves.push_back(leaf);
+
+        for index in 0..leaves.len() {
+            let proof = client.mmr_proof(&(index as u64));
+            let leaf = leaves.get_unchecked(index);
+            assert!(client.mmr_verify(&leaf, &proof), "Error in mmr proof");
+
+            let other = BytesN::from_array(&env, &[0xff; 32]);
+            assert!(!client.mmr_verify(&other, &proof), "Error in wrong leaf");
+        }
+    }
+
+    // 11 leaves are peaks of 8, 2 and 1 leaves, bagged right to left.
+    let node = |a: u32, b: u32| hash(&leaves.get_unchecked(a), &leaves.get_unchecked(b));
+    let peak_8 = hash(
+        &hash(&node(0, 1), &node(2, 3)),
+        &hash(&node(4, 5), &node(6, 7)),
+    );
+    let peaks = vec![&env, peak_8.clone(), node(8, 9), leaves.get_unchecked(10)];
+    let root = hash(&peak_8, &hash(&node(8, 9), &leaves.get_unchecked(10)));
+    let proof: MmrProof = client.mmr_proof(&9);
+    assert!(
+        proof.peaks == peaks && proof.siblings.len() == 1,
+        "Error in peaks"
+    );
+    assert!(client.mmr_root() == root, "Error in bagged root");
+
+    // The range is independent of the fixed-depth tree.
+    assert!(client.get_tree().count == 0, "Error in tree count");
+    let mut mmr = Mmr::new(&env);
+    for leaf in leaves.iter() {
+        mmr.append(&env, leaf);
+    }
+    assert!(
+        mmr.root(&env) == root && mmr.count() == 11,
+        "Error in standalone mmr"
+    );
+}
+
+#[test]
+fn verify_range_checks_contiguous_leaves() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 7);
+
+    let range_paths = |start: u32, end: u32| {
+        let first = client.portable_proof(&start);
+        let last = client.portable_proof(&(end - 1));
+        let (mut left, mut right) = (vec![&env], vec![&env]);
+        for level in 0..32 {
+            if (start >> level) & 1 == 1 {
+                left.push_back(first.branch.get_unchecked(level));
+            }
+            if ((end - 1) >> level) & 1 == 0 {
+                right.push_back(last.branch.get_unchecked(level));
+            }
+        }
+        (left, right)
+    };
+    let leaves = |start: u8, end: u8| {
+        let mut leaves = vec![&env];
+        for i in start..end {
+            leaves.push_back(BytesN::from_array(&env, &[i + 1; 32]));
+        }
+        leaves
+    };
+
+    for (start, end) in [(1u32, 5u32), (0, 7), (4, 5), (2, 4)] {
+        let (left, right) = range_paths(start, end);
+        let range = leaves(start as u8, end as u8);
+        assert!(
+            client.verify_range(&range, &start, &left, &right),
+            "Error in valid range"
+        );
+    }
+
+    let (left, right) = range_paths(1, 5);
+    let mut tampered = leaves(1, 5);
+    tampered.set(2, BytesN::from_array(&env, &[0xee; 32]));
+    assert!(
+        !client.verify_range(&tampered, &1, &left, &right),
+        "Error tampered leaf accepted"
+    );
+    assert!(
+        !client.verify_range(&leaves(1, 5), &2, &left, &right),
+        "Error wrong start accepted"
+    );
+
+    let mut extra = right.clone();
+    extra.push_back(BytesN::from_array(&env, &[0; 32]));
+    assert!(
+        !client.verify_range(&leaves(1, 5), &1, &left, &extra),
+        "Error unused sibling accepted"
+    );
+}
+
+#[test]
+fn genesis_leaf_occupies_index_zero() {
+    let env = Env::default();
+    let genesis = BytesN::from_array(&env, &[0x9e; 32]);
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            genesis: Some(genesis.clone()),
+            ..TreeConfig::default()
+        },
+    );
+
+    assert!(client.get_tree().count == 1, "Error in genesis count");
+    assert!(
+        !client.is_empty_root(&client.get_root()),
+        "Error genesis tree is empty"
+    );
+    assert!(
+        client.portable_proof(&0).leaf == genesis,
+        "Error in genesis leaf"
+    );
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    client.insert(&leaf);
+    assert!(
+        client.portable_proof(&1).leaf == leaf,
+        "Error in first inserted leaf"
+    );
+
+    let reference = setup_with_config(&env, TreeConfig::default());
+    reference.insert(&genesis);
+    reference.insert(&leaf);
+    assert!(
+        client.get_root() == reference.get_root(),
+        "Error in root after genesis"
+    );
+}
+
+#[test]
+fn dual_root_matches_single_algorithm_trees() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+    let reference = setup_with_config(&env, TreeConfig::default());
+
+    let sha256_pair = |left: [u8; 32], right: [u8; 32]| {
+        let mut pair = Bytes::from_array(&env, &left);
+        pair.append(&Bytes::from_array(&env, &right));
+        env.crypto().sha256(&pair).to_array()
+    };
+
+    let mut leaves = [[0u8; 32]; 5];
+    for i in 0..5u8 {
+        leaves[i as usize] = [i + 1; 32];
+        reference.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+
+    // Independent SHA-256 tree: hash the five leaves up, then climb the
+    // zero tower with the node on the left.
+    let zero = [0u8; 32];
+    let l1 = [
+        sha256_pair(leaves[0], leaves[1]),
+        sha256_pair(leaves[2], leaves[3]),
+        sha256_pair(leaves[4], zero),
+    ];
+    let z1 = sha256_pair(zero, zero);
+    let l2 = [sha256_pair(l1[0], l1[1]), sha256_pair(l1[2], z1)];
+    let mut node = sha256_pair(l2[0], l2[1]);
+    let mut zero_level = sha256_pair(z1, z1);
+    for _ in 3..32 {
+        zero_level = sha256_pair(zero_level, zero_level);
+        node = sha256_pair(node, zero_level);
+    }
+
+    let (keccak, sha256) = client.dual_root();
+    assert!(keccak == reference.get_root(), "Error in keccak root");
+    assert!(sha256.to_array() == node, "Error in sha256 root");
+    assert!(keccak != sha256, "Error in distinct roots");
+}
+
+#[test]
+fn verify_single_checks_sole_leaf() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let other = BytesN::from_array(&env, &[2; 32]);
+    assert!(!client.verify_single(&leaf), "Error in empty tree");
+
+    client.insert(&leaf);
+    assert!(client.verify_single(&leaf), "Error in sole leaf");
+    assert!(!client.verify_single(&other), "Error in other leaf");
+    assert!(
+        MerkleTree::root_of_single(env.clone(), leaf.clone()) == client.get_root(),
+        "Error in single leaf root"
+    );
+
+    client.insert(&other);
+    assert!(!client.verify_single(&leaf), "Error leaf no longer alone");
+}
+
+#[test]
+fn get_tree_fails_on_truncated_frontier() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    for i in 0..5u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+
+    // Five leaves need the level 2 node, keep only levels 0 and 1.
+    let mut tree = client.get_tree();
+    tree.branch.pop_back();
+    env.as_contract(&contract_id, || {
+        env.storage().persistent().set(&TREE, &tree);
+    });
+
+    assert!(
+        client.try_get_tree() == Err(Ok(Error::FrontierInconsistent.into())),
+        "Error reading truncated frontier"
+    );
+    assert!(
+        client.try_get_root() == Err(Ok(Error::FrontierInconsistent.into())),
+        "Error root of truncated frontier"
+    );
+}
+
+#[test]
+fn verify_against_tag_uses_pinned_root() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+    env.mock_all_auths();
+    let owner = Address::random(&env);
+    let other = Address::random(&env);
+    let epoch = BytesN::from_array(&env, &[5; 32]);
+
+    client.commit_root_reference(&owner, &epoch);
+    let old = client.portable_proof(&1);
+    client.insert(&BytesN::from_array(&env, &[4; 32]));
+    let new = client.portable_proof(&1);
+
+    assert!(
+        client.verify_against_tag(&owner, &epoch, &old.leaf, &old.branch, &leaf_index(1)),
+        "Error in old proof against tag"
+    );
+    assert!(
+        !client.verify_against_tag(&owner, &epoch, &new.leaf, &new.branch, &leaf_index(1)),
+        "Error new proof against old tag"
+    );
+    let unknown = BytesN::from_array(&env, &[6; 32]);
+    assert!(
+        !client.verify_against_tag(&owner, &unknown, &old.leaf, &old.branch, &leaf_index(1)),
+        "Error in unknown tag"
+    );
+    assert!(
+        client.try_commit_root_reference(&owner, &epoch)
+            == Err(Ok(Error::TagAlreadyCommitted.into())),
+        "Error tag moved"
+    );
+
+    // Another caller's tag of the same name is its own slot
+    client.commit_root_reference(&other, &epoch);
+    assert!(
+        client.verify_against_tag(&other, &epoch, &new.leaf, &new.branch, &leaf_index(1)),
+        "Error in other owner's tag"
+    );
+    assert!(
+        client.verify_against_tag(&owner, &epoch, &old.leaf, &old.branch, &leaf_index(1)),
+        "Error tag squatted by another owner"
+    );
+}
+
+#[test]
+fn step_root_mirrors_inserts() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+
+    let mut frontier = vec![&env];
+    let mut count = 0;
+    for i in 0..9u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        let (next, next_count, root) = client.step_root(&frontier, &count, &leaf);
+        let tree = client.insert(&leaf);
+
+        assert!(
+            next == tree.branch && next_count == tree.count,
+            "Error in stepped state"
+        );
+        assert!(root == client.get_root(), "Error in stepped root");
+        frontier = next;
+        count = next_count;
+    }
+
+    frontier.pop_back();
+    let leaf = BytesN::from_array(&env, &[10; 32]);
+    assert!(
+        client.try_step_root(&frontier, &count, &leaf)
+            == Err(Ok(Error::FrontierInconsistent.into())),
+        "Error short frontier accepted"
+    );
+}
+
+#[test]
+fn audit_against_reports_first_divergence() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 6);
+    let mut leaves = vec![&env];
+    for i in 0..6u8 {
+        leaves.push_back(BytesN::from_array(&env, &[i + 1; 32]));
+    }
+
+    let audit: AuditResult = client.audit_against(&leaves);
+    assert!(
+        audit.matches && audit.first_divergence.is_none(),
+        "Error in matching set"
+    );
+    assert!(
+        audit.computed_root == audit.expected_root,
+        "Error in matching roots"
+    );
+
+    let mut differing = leaves.clone();
+    differing.set(3, BytesN::from_array(&env, &[0xdd; 32]));
+    let audit = client.audit_against(&differing);
+    assert!(
+        !audit.matches && audit.first_divergence == Some(3),
+        "Error in differing set"
+    );
+    assert!(
+        audit.expected_root == client.get_root(),
+        "Error in expected root"
+    );
+
+    leaves.pop_back();
+    let audit = client.audit_against(&leaves);
+    assert!(audit.first_divergence == Some(5), "Error in missing leaf");
+}
+
+#[test]
+fn empty_leaf_sentinel_rebuilds_zero_tower() {
+    let env = Env::default();
+    let sentinel = MerkleTree::keccak256_bytes(&Bytes::from_slice(&env, b"empty"));
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            empty_leaf: Some(BytesN::from_array(&env, &sentinel)),
+            ..TreeConfig::default()
+        },
+    );
+
+    let mut tower = vec![&env];
+    let mut node = sentinel;
+    for _ in 0..32 {
+        tower.push_back(BytesN::from_array(&env, &node));
+        node = MerkleTree::keccak256(vec![&env, node, node]);
+    }
+    let zeroes = MerkleTree::zero_hashes_from(env.clone(), BytesN::from_array(&env, &sentinel));
+    assert!(zeroes == tower, "Error in zero hash tower");
+
+    let empty_root = client.get_root();
+    assert!(empty_root.to_array() == node, "Error in empty tree root");
+    assert!(client.is_empty_root(&empty_root), "Error in is_empty_root");
+    assert!(
+        empty_root != MerkleTree::empty_root(env.clone()),
+        "Error sentinel ignored"
+    );
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    client.insert(&leaf);
+    let root = MerkleTree::branch_root(env.clone(), leaf.clone(), tower, leaf_index(0u64));
+    assert!(client.get_root() == root, "Error in root with sentinel");
+    assert!(
+        client.verify_single(&leaf),
+        "Error in single leaf with sentinel"
+    );
+
+    client.insert(&BytesN::from_array(&env, &[2; 32]));
+    client.insert(&BytesN::from_array(&env, &[3; 32]));
+    assert_tree_invariants(&env, &client);
+}
+
+#[test]
+fn insert_full_is_internally_consistent() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 2);
+
+    let leaf = BytesN::from_array(&env, &[3; 32]);
+    let result: InsertResult = client.insert_full(&leaf);
+    assert_tree_invariants(&env, &client);
+    assert!(
+        result.index == 2 && result.count == result.index as u64 + 1,
+        "Error in index and count"
+    );
+    assert!(result.root == client.get_root(), "Error in root");
+
+    let proof = client.portable_proof(&result.index);
+    assert!(
+        proof.leaf == leaf && proof.root == result.root,
+        "Error in proof of inserted leaf"
+    );
+}
+
+#[test]
+fn insert_returns_branch_root_index() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 0);
+
+    let mut tree = MerkleTree::new(&env);
+    for i in 0..6u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        let index = client.insert_and_get_index(&leaf);
+        assert_tree_invariants(&env, &client);
+        assert!(
+            index == i as u32 && tree.insert(env.clone(), leaf.clone()) == index,
+            "Error in index"
+        );
+
+        let proof = client.portable_proof(&index);
+        let root =
+            MerkleTree::branch_root(env.clone(), leaf, proof.branch, leaf_index(index as u64));
+        assert!(root == client.get_root(), "Error proof at returned index");
+    }
+}
+
+#[test]
+fn get_count_returns_inserted_leaves() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    assert!(client.get_count() == 0, "Error in empty count");
+
+    for i in 0..3u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        let next = client.get_count();
+        assert!(
+            client.insert_and_get_index(&leaf) as u64 == next,
+            "Error in next index"
+        );
+        assert!(
+            client.get_tree().count() == client.get_count(),
+            "Error in tree count"
+        );
+        assert_tree_invariants(&env, &client);
+    }
+    assert!(client.get_count() == 3, "Error in count");
+}
+
+#[test]
+fn verify_in_either_accepts_old_or_new_tree() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    let namespaces = (symbol_short!("old"), symbol_short!("new"));
+
+    let old_leaf = BytesN::from_array(&env, &[1; 32]);
+    let new_leaf = BytesN::from_array(&env, &[2; 32]);
+    let other = BytesN::from_array(&env, &[3; 32]);
+    client.insert_into(&namespaces.0, &old_leaf);
+    client.insert_into(&namespaces.1, &other);
+    client.insert_into(&namespaces.1, &new_leaf);
+
+    let old_proof = MerkleTree::zero_hashes(env.clone());
+    let new_proof = zero_padded_proof(&env, vec![&env, other.clone()]);
+    assert!(
+        client.verify_in_either(&namespaces, &old_leaf, &old_proof, &leaf_index(0)),
+        "Error in old tree"
+    );
+    assert!(
+        client.verify_in_either(&namespaces, &new_leaf, &new_proof, &leaf_index(1)),
+        "Error in new tree"
+    );
+    assert!(
+        !client.verify_in_either(&namespaces, &other, &old_proof, &leaf_index(0)),
+        "Error in neither tree"
+    );
+
+    // Namespaced trees leave the main one alone.
+    assert!(client.get_count() == 0, "Error in main tree");
+}
+
+#[test]
+fn root_after_replace_requires_valid_old_proof() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 4);
+    let proof = client.portable_proof(&2);
+    let new_item = BytesN::from_array(&env, &[9; 32]);
+
+    let replaced = client.root_after_replace(&proof.leaf, &new_item, &proof.branch, &2);
+
+    let expected = setup_with_config(&env, TreeConfig::default());
+    for leaf in [1u8, 2, 9, 4] {
+        expected.insert(&BytesN::from_array(&env, &[leaf; 32]));
+    }
+    assert!(
+        replaced == Some(expected.get_root()),
+        "Error in replaced root"
+    );
+    assert!(client.get_root() == proof.root, "Error tree changed");
+
+    let invalid = client.root_after_replace(&new_item, &proof.leaf, &proof.branch, &2);
+    assert!(invalid.is_none(), "Error invalid old proof accepted");
+}
+
+#[test]
+fn zero_hashes_table_matches_computed_tower() {
+    let env = Env::default();
+    let _zero = BytesN::from_array(&env, &[0; 32]);
+
+    assert!(
+        MerkleTree::zero_hashes(env.clone()) == MerkleTree::zero_hashes_from(env.clone(), _zero),
+        "Error zero hashes table differs from the keccak tower"
+    );
+    assert!(
+        MerkleTree::empty_root(env.clone()).to_array()
+            == [
+                0x27, 0xae, 0x5b, 0xa0, 0x8d, 0x72, 0x91, 0xc9, 0x6c, 0x8c, 0xbd, 0xdc, 0xc1, 0x48,
+                0xbf, 0x48, 0xa6, 0xd6, 0x8c, 0x79, 0x74, 0xb9, 0x43, 0x56, 0xf5, 0x37, 0x54, 0xef,
+                0x61, 0x71, 0xd7, 0x57
+            ],
+        "Error empty root changed"
+    );
+}
+
+#[test]
+fn generate_proof_feeds_branch_root() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+
+    for index in 0..5u32 {
+        let branch = client.generate_proof(&index);
+        let leaf = BytesN::from_array(&env, &[index as u8 + 1; 32]);
+
+        assert!(branch.len() == 32, "Error in proof length");
+        assert!(
+            MerkleTree::branch_root(env.clone(), leaf, branch, leaf_index(index as u64))
+                == client.get_root(),
+            "Error in generated proof"
+        );
+    }
+    assert!(
+        client.try_generate_proof(&5) == Err(Ok(Error::InvalidRange.into())),
+        "Error proof past count"
+    );
+
+    let client = setup_with_config(&env, TreeConfig::default());
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(
+        client.try_generate_proof(&0) == Err(Ok(Error::LeavesNotStored.into())),
+        "Error proof without stored leaves"
+    );
+}
+
+#[test]
+fn config_fingerprint_tracks_configuration() {
+    let env = Env::default();
+    let config = TreeConfig {
+        store_leaves: true,
+        salt: Some(BytesN::from_array(&env, &[7; 32])),
+        ..TreeConfig::default()
+    };
+
+    let first = setup_with_config(&env, config.clone()).config_fingerprint();
+    let second = setup_with_config(&env, config.clone()).config_fingerprint();
+    assert!(first == second, "Error in identical configurations");
+
+    let admin = TreeConfig {
+        admin: Some(Address::random(&env)),
+        ..config.clone()
+    };
+    assert!(
+        setup_with_config(&env, admin).config_fingerprint() == first,
+        "Error admin changed the fingerprint"
+    );
+
+    let differing = [
+        TreeConfig {
+            store_leaves: false,
+            ..config.clone()
+        },
+        TreeConfig {
+            salt: None,
+            ..config.clone()
+        },
+        TreeConfig {
+            salt: Some(BytesN::from_array(&env, &[0; 32])),
+            ..config.clone()
+        },
+        TreeConfig {
+            fanout: 4,
+            ..config.clone()
+        },
+        TreeConfig {
+            empty_leaf: Some(BytesN::from_array(&env, &[1; 32])),
+            ..config.clone()
+        },
+    ];
+    for other in differing.iter() {
+        assert!(
+            setup_with_config(&env, other.clone()).config_fingerprint() != first,
+            "Error in differing configuration"
+        );
+    }
+}
+
+#[test]
+fn verify_checks_current_and_past_roots() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    let branch = client.generate_proof(&1);
+    let past_root = client.get_root();
+
+    assert!(
+        client.verify(&leaf, &branch, &leaf_index(1)),
+        "Error in valid proof"
+    );
+    assert!(
+        !client.verify(&leaf, &branch, &leaf_index(2)),
+        "Error in wrong index"
+    );
+
+    client.insert(&BytesN::from_array(&env, &[4; 32]));
+    assert!(
+        !client.verify(&leaf, &branch, &leaf_index(1)),
+        "Error in stale proof"
+    );
+    assert!(
+        MerkleTree::verify_against(
+            env.clone(),
+            leaf.clone(),
+            branch.clone(),
+            leaf_index(1),
+            past_root
+        ),
+        "Error in past root"
+    );
+}
+
+#[test]
+fn verify_lenient_pads_with_zero_hashes() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+
+    for index in 0..5u64 {
+        let leaf = BytesN::from_array(&env, &[index as u8 + 1; 32]);
+        let full = client.generate_proof(&(index as u32));
+        // 5 leaves fill the first 3 levels, every sibling above is a zero hash
+        let mut short = full.clone();
+        while short.len() > 3 {
+            short.pop_back_unchecked();
+        }
+
+        assert!(
+            client.verify(&leaf, &full, &leaf_index(index)),
+            "Error in full proof"
+        );
+        assert!(
+            client.verify_lenient(&leaf, &full, &leaf_index(index)),
+            "Error in lenient full proof"
+        );
+        assert!(
+            client.verify_lenient(&leaf, &short, &leaf_index(index)),
+            "Error in lenient short proof"
+        );
+        assert!(
+            client.try_verify(&leaf, &short, &leaf_index(index))
+                == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+            "Error short proof accepted"
+        );
+    }
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let mut cut = client.generate_proof(&0);
+    cut.pop_front_unchecked();
+    assert!(
+        !client.verify_lenient(&leaf, &cut, &leaf_index(0)),
+        "Error in proof missing a low level"
+    );
+}
+
+#[test]
+fn tree_holds_exactly_max_leaves() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    let _zeroes = MerkleTree::zero_hashes(env.clone());
+    let leaf = BytesN::from_array(&env, &[5; 32]);
+    let last = u32::MAX as u64;
+
+    // A frontier of zero hashes is a tree whose first 2^32 - 1 leaves are empty
+    let (frontier, count, root) = client.step_root(&_zeroes, &last, &leaf);
+    assert!(count == 1 << 32, "Error in full count");
+    assert!(frontier.len() == 33, "Error in full frontier");
+    assert!(
+        root == MerkleTree::branch_root(env.clone(), leaf.clone(), _zeroes, leaf_index(last)),
+        "Error in full root"
+    );
+    assert!(
+        client.try_step_root(&frontier, &count, &leaf) == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error insert past capacity"
+    );
+
+    // Same boundary for a 4-ary tree, whose top level fills up last
+    let mut _branch = vec![&env];
+    let mut _siblings = vec![&env];
+    let mut _zero = BytesN::from_array(&env, &[0; 32]);
+    for _ in 0..KaryTree::kary_depth(4) {
+        let _level = vec![&env, _zero.clone(), _zero.clone(), _zero.clone()];
+        _branch.push_back(_level.clone());
+        _siblings.push_back(_level.clone());
+        let mut _children = _level;
+        _children.push_back(_zero.clone());
+        _zero = KaryTree::hash_children(&env, &_children);
+    }
+    let mut kary = KaryTree {
+        fanout: 4,
+        branch: _branch,
+        count: last,
+    };
+    kary.insert(env.clone(), leaf.clone());
+    assert!(
+        kary.root(env.clone()) == KaryTree::branch_root(env.clone(), 4, leaf, _siblings, last),
+        "Error in full kary root"
+    );
+}
+
+#[test]
+fn prove_empty_only_on_fresh_tree() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    assert!(client.prove_empty(), "Error in fresh tree");
+
+    client.insert(&BytesN::from_array(&env, &[0; 32]));
+    assert!(!client.prove_empty(), "Error after an insert");
+
+    let genesis = TreeConfig {
+        genesis: Some(BytesN::from_array(&env, &[1; 32])),
+        ..TreeConfig::default()
+    };
+    assert!(
+        !setup_with_config(&env, genesis).prove_empty(),
+        "Error with a genesis leaf"
+    );
+}
+
+#[test]
+fn small_depth_tree_fills_up_to_capacity() {
+    let env = Env::default();
+    let mut tree = MerkleTree::with_depth(&env, 4);
+    assert!(tree.capacity() == 16, "Error in capacity");
+    assert!(
+        MerkleTree::zero_hashes_at(env.clone(), HashAlgo::Keccak256, 4).len() == 4,
+        "Error in zero hashes length"
+    );
+
+    let mut levels = vec![&env, vec![&env]];
+    for i in 0..16u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        assert!(
+            tree.insert(env.clone(), leaf.clone()) == i as u32,
+            "Error in index"
+        );
+        let mut level = levels.get_unchecked(0);
+        level.push_back(leaf);
+        levels.set(0, level);
+    }
+    assert!(tree.count() == tree.capacity(), "Error in full count");
+
+    for height in 0..4 {
+        let nodes = levels.get_unchecked(height);
+        let mut parents = vec![&env];
+        for i in (0..nodes.len()).step_by(2) {
+            let _vec = vec![
+                &env,
+                nodes.get_unchecked(i).to_array(),
+                nodes.get_unchecked(i + 1).to_array(),
+            ];
+            parents.push_back(BytesN::from_array(&env, &MerkleTree::keccak256(_vec)));
+        }
+        levels.push_back(parents);
+    }
+    let root = levels.get_unchecked(4).get_unchecked(0);
+    assert!(tree.root(env.clone()) == root, "Error in full root");
+
+    let index = 5u32;
+    let mut branch = vec![&env];
+    for height in 0..4 {
+        let sibling = (index >> height) ^ 1;
+        branch.push_back(levels.get_unchecked(height).get_unchecked(sibling));
+    }
+    let leaf = BytesN::from_array(&env, &[6; 32]);
+    assert!(
+        MerkleTree::branch_root_at_depth(env.clone(), 4, leaf, branch, leaf_index(index as u64))
+            == root,
+        "Error in proof"
+    );
+}
+
+#[test]
+#[should_panic]
+fn small_depth_tree_rejects_leaf_past_capacity() {
+    let env = Env::default();
+    let mut tree = MerkleTree::with_depth(&env, 2);
+    for i in 0..5u8 {
+        tree.insert(env.clone(), BytesN::from_array(&env, &[i; 32]));
+    }
+}
+
+#[test]
+#[should_panic]
+fn tree_depth_above_limit_fails() {
+    let env = Env::default();
+    MerkleTree::with_depth(&env, 33);
+}
+
+#[test]
+fn capacity_helpers_track_inserts() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    assert!(!client.is_full(), "Error empty tree full");
+    assert!(
+        client.remaining_capacity() == 1 << 32,
+        "Error in empty capacity"
+    );
+
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert_tree_invariants(&env, &client);
+    assert!(
+        client.remaining_capacity() == (1 << 32) - 1,
+        "Error in capacity after insert"
+    );
+
+    let mut tree = MerkleTree::with_depth(&env, 1);
+    for i in 0..2u8 {
+        assert!(!tree.is_full(), "Error tree full early");
+        tree.insert(env.clone(), BytesN::from_array(&env, &[i; 32]));
+    }
+    assert!(tree.is_full(), "Error in full tree");
+    assert!(tree.remaining_capacity() == 0, "Error in full capacity");
+}
+
+#[test]
+fn index_binding_rejects_proof_at_other_index() {
+    let env = Env::default();
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+
+    // Two equal sibling leaves share a path, so a plain proof replays
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            ..TreeConfig::default()
+        },
+    );
+    client.insert(&leaf);
+    client.insert(&leaf);
+    let branch = client.generate_proof(&0);
+    assert!(
+        client.verify(&leaf, &branch, &leaf_index(1)),
+        "Error in plain replay"
+    );
+
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            bind_index: true,
+            ..TreeConfig::default()
+        },
+    );
+    client.insert(&leaf);
+    client.insert(&leaf);
+    let branch = client.generate_proof(&0);
+
+    assert!(
+        client.verify(&leaf, &branch, &leaf_index(0)),
+        "Error in bound proof"
+    );
+    assert!(
+        !client.verify(&leaf, &branch, &leaf_index(1)),
+        "Error bound proof replayed"
+    );
+    assert!(
+        client.generate_proof(&0) != setup_with_leaves(&env, 2).generate_proof(&0),
+        "Error leaves not bound"
+    );
+}
+
+#[test]
+fn leaves_until_stable_counts_down_to_completion() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+
+    // With 5 leaves, the pairs [0, 2) and [2, 4) and the block [0, 4) are done
+    let expected = [3u32, 3, 3, 3, 1];
+    for (index, remaining) in expected.iter().enumerate() {
+        assert!(
+            client.leaves_until_stable(&(index as u32)) == *remaining,
+            "Error in countdown"
+        );
+    }
+    assert!(
+        client.try_leaves_until_stable(&5) == Err(Ok(Error::InvalidRange.into())),
+        "Error index past count"
+    );
+
+    // Level 2 of the proof of 0 still changes until 3 more leaves arrive
+    let before = client.generate_proof(&0).get_unchecked(2);
+    for i in 0..3u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 6; 32]));
+    }
+    let frozen = client.generate_proof(&0).get_unchecked(2);
+    assert!(before != frozen, "Error in sibling before completion");
+    assert!(
+        client.leaves_until_stable(&0) == 8,
+        "Error in next countdown"
+    );
+
+    client.insert(&BytesN::from_array(&env, &[9; 32]));
+    assert!(
+        client.generate_proof(&0).get_unchecked(2) == frozen,
+        "Error in frozen sibling"
+    );
+    assert!(client.leaves_until_stable(&8) == 1, "Error in new leaf");
+}
+
+#[test]
+fn sha256_tree_matches_sha256_root() {
+    let env = Env::default();
+    let mut tree = MerkleTree::with_hash_algo(&env, 32, HashAlgo::Sha256);
+    let mut leaves = vec![&env];
+    for i in 0..5u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        tree.insert(env.clone(), leaf.clone());
+        leaves.push_back(leaf);
+    }
+
+    let root = tree.root(env.clone());
+    assert!(
+        root == MerkleTree::sha256_root(env.clone(), leaves),
+        "Error in sha256 tree root"
+    );
+
+    let mut tower = vec![&env];
+    let mut zero = BytesN::from_array(&env, &[0; 32]);
+    for _ in 0..32 {
+        tower.push_back(zero.clone());
+        zero = MerkleTree::hash_pair(&env, HashAlgo::Sha256, &zero, &zero);
+    }
+    let zeroes = MerkleTree::zero_hashes_at(env.clone(), HashAlgo::Sha256, 32);
+    assert!(zeroes == tower, "Error in sha256 zero hashes");
+    assert!(
+        tree.root_with_ctx(env.clone(), &zeroes.get_unchecked(0), zeroes.clone()) == root,
+        "Error in root with sha256 zero hashes"
+    );
+
+    // Leaf 4 is alone in its pair, and the first four leaves fill the block
+    let mut branch = tower.clone();
+    let pair = |l: u8, r: u8| {
+        MerkleTree::hash_pair(
+            &env,
+            HashAlgo::Sha256,
+            &BytesN::from_array(&env, &[l; 32]),
+            &BytesN::from_array(&env, &[r; 32]),
+        )
+    };
+    let block = MerkleTree::hash_pair(&env, HashAlgo::Sha256, &pair(1, 2), &pair(3, 4));
+    branch.set(2, block);
+    let leaf = BytesN::from_array(&env, &[5; 32]);
+    assert!(
+        MerkleTree::branch_root_with(
+            env.clone(),
+            HashAlgo::Sha256,
+            32,
+            leaf,
+            branch,
+            leaf_index(4u64)
+        ) == root,
+        "Error in sha256 proof"
+    );
+}
+
+#[test]
+fn verify_nested_combines_inner_and_outer_proofs() {
+    let env = Env::default();
+    let leaf = |i: u8| BytesN::from_array(&env, &[i; 32]);
+    let pair =
+        |l: &BytesN<32>, r: &BytesN<32>| MerkleTree::hash_pair(&env, HashAlgo::Keccak256, l, r);
+
+    let mut inner = MerkleTree::with_depth(&env, 4);
+    for i in 1..4u8 {
+        inner.insert(env.clone(), leaf(i));
+    }
+    let client = setup_with_leaves(&env, 2);
+    client.insert(&inner.root(env.clone()));
+    let outer_branch = client.generate_proof(&2);
+
+    // Proof of leaf 2 at inner index 1, the zero hashes above its pairs
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+    let inner_branch = vec![
+        &env,
+        leaf(1),
+        pair(&leaf(3), &zeroes.get_unchecked(0)),
+        zeroes.get_unchecked(2),
+        zeroes.get_unchecked(3),
+    ];
+    assert!(
+        client.verify_nested(
+            &leaf(2),
+            &inner_branch,
+            &leaf_index(1),
+            &outer_branch,
+            &leaf_index(2)
+        ),
+        "Error in nested proof"
+    );
+    assert!(
+        !client.verify_nested(
+            &leaf(2),
+            &inner_branch,
+            &leaf_index(1),
+            &outer_branch,
+            &leaf_index(1)
+        ),
+        "Error in wrong outer index"
+    );
+
+    let mut tampered = inner_branch.clone();
+    tampered.set(0, leaf(9));
+    assert!(
+        !client.verify_nested(
+            &leaf(2),
+            &tampered,
+            &leaf_index(1),
+            &outer_branch,
+            &leaf_index(2)
+        ),
+        "Error tampered inner proof accepted"
+    );
+    assert!(
+        client.try_verify_nested(
+            &leaf(2),
+            &vec![&env],
+            &leaf_index(0),
+            &outer_branch,
+            &leaf_index(2)
+        ) == Err(Ok(Error::InvalidDepth.into())),
+        "Error empty inner proof accepted"
+    );
+}
+
+#[test]
+fn state_commitment_follows_count_endianness() {
+    let env = Env::default();
+    let commit = |endianness: Endianness| {
+        let client = setup_with_config(
+            &env,
+            TreeConfig {
+                count_endianness: endianness,
+                ..TreeConfig::default()
+            },
+        );
+        for i in 0..3u8 {
+            client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+        }
+        (client.get_root().to_array(), client.state_commitment())
+    };
+    let (root, big) = commit(Endianness::Big);
+    let (_, little) = commit(Endianness::Little);
+
+    let mut word = [0u8; 32];
+    word[31] = 3;
+    let expected = MerkleTree::keccak256(vec![&env, root, word]);
+    assert!(big.to_array() == expected, "Error in big-endian layout");
+
+    let mut word = [0u8; 32];
+    word[0] = 3;
+    let expected = MerkleTree::keccak256(vec![&env, root, word]);
+    assert!(
+        little.to_array() == expected,
+        "Error in little-endian layout"
+    );
+    assert!(big != little, "Error in distinct commitments");
+}
+
+/// Returns how many times `MerkleTree::keccak256` ran on this thread during `f`.
+fn count_keccak_calls(f: impl FnOnce()) -> u64 {
+    let before = crate::KECCAK_CALLS.with(|calls| calls.get());
+    f();
+    crate::KECCAK_CALLS.with(|calls| calls.get()) - before
+}
+
+#[test]
+fn insert_is_amortized_constant_in_hashes() {
+    let env = Env::default();
+    let mut tree = MerkleTree::new(&env);
+    let inserts = 4096u64;
+
+    // Insert `n` carries through `trailing_zeros(n)` levels, which averages
+    // under one hash per insert, where an O(depth) insert would take 32.
+    let hashes = count_keccak_calls(|| {
+        for i in 0..inserts {
+            let mut leaf = [0u8; 32];
+            leaf[24..].copy_from_slice(&i.to_be_bytes());
+            tree.insert(env.clone(), BytesN::from_array(&env, &leaf));
+        }
+    });
+    assert!(hashes == inserts - 1, "Error in total hashes");
+    assert!(hashes < 2 * inserts, "Error insert not amortized constant");
+}
+
+#[test]
+fn root_history_keeps_recent_roots() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    let empty = client.get_root();
+    assert!(client.is_known_root(&empty), "Error in empty root");
+    assert!(
+        client.latest_checkpoint() == (0, empty.clone()),
+        "Error in empty checkpoint"
+    );
+
+    let mut roots = vec![&env];
+    for i in 0..40u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+        roots.push_back(client.get_root());
+    }
+    assert!(
+        client.latest_checkpoint() == (40, client.get_root()),
+        "Error in latest checkpoint"
+    );
+
+    // Counts 9 to 40 are the last 32 checkpoints
+    for (i, root) in roots.iter().enumerate() {
+        assert!(
+            client.is_known_root(&root) == (i >= 8),
+            "Error in known roots"
+        );
+    }
+    assert!(!client.is_known_root(&empty), "Error in evicted empty root");
+}
+
+#[test]
+fn verification_bundle_is_self_consistent() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+    let bundle: VerificationBundle = client.verification_bundle();
+
+    assert!(bundle.root == client.get_root(), "Error in root");
+    assert!(bundle.count == 3 && bundle.depth == 32, "Error in shape");
+    assert!(
+        bundle.hash_algo == HashAlgo::Keccak256 && !bundle.bind_index,
+        "Error in hashing"
+    );
+
+    // The tower must regenerate itself and the empty root
+    let mut zero = bundle.zero_hashes.get_unchecked(0);
+    for level in 0..bundle.depth {
+        assert!(
+            bundle.zero_hashes.get_unchecked(level) == zero,
+            "Error in zero hash tower"
+        );
+        zero = MerkleTree::hash_pair(&env, bundle.hash_algo, &zero, &zero);
+    }
+    assert!(
+        MerkleTree::empty_root(env.clone()) == zero,
+        "Error in empty root"
+    );
+
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    let branch = client.generate_proof(&1);
+    assert!(
+        MerkleTree::branch_root_with(
+            env.clone(),
+            bundle.hash_algo,
+            bundle.depth,
+            leaf,
+            branch,
+            leaf_index(1u64)
+        ) == bundle.root,
+        "Error in proof against bundle"
+    );
+}
+
+#[test]
+fn branch_root_accepts_indices_up_to_the_last_leaf() {
+    let env = Env::default();
+    let leaves: [BytesN<32>; 4] =
+        core::array::from_fn(|i| BytesN::from_array(&env, &[i as u8 + 1; 32]));
+    let mut tree = MerkleTree::with_depth(&env, 2);
+    for leaf in leaves.iter() {
+        tree.insert(env.clone(), leaf.clone());
+    }
+    let pair = MerkleTree::hash_pair(&env, HashAlgo::Keccak256, &leaves[0], &leaves[1]);
+
+    let first = vec![
+        &env,
+        leaves[1].clone(),
+        MerkleTree::hash_pair(&env, HashAlgo::Keccak256, &leaves[2], &leaves[3]),
+    ];
+    assert!(
+        MerkleTree::branch_root_at_depth(env.clone(), 2, leaves[0].clone(), first, leaf_index(0))
+            == tree.root(env.clone()),
+        "Error in first index"
+    );
+    let last = vec![&env, leaves[2].clone(), pair];
+    assert!(
+        MerkleTree::branch_root_at_depth(env.clone(), 2, leaves[3].clone(), last, leaf_index(3))
+            == tree.root(env.clone()),
+        "Error in last index"
+    );
+}
+
+#[test]
+#[should_panic]
+fn branch_root_rejects_index_past_the_last_leaf() {
+    let env = Env::default();
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let branch = vec![&env, leaf.clone(), leaf.clone()];
+    MerkleTree::branch_root_at_depth(env.clone(), 2, leaf, branch, leaf_index(4));
+}
+
+#[test]
+fn verify_rejects_index_past_the_tree() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 1);
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let branch = MerkleTree::zero_hashes(env.clone());
+
+    assert!(
+        client.verify(&leaf, &branch, &leaf_index(0)),
+        "Error in first index"
+    );
+    assert!(
+        client.try_verify(&leaf, &branch, &leaf_index(u64::from(u32::MAX)))
+            == Err(Ok(Error::MerkleTreeIndexOutOfRange.into())),
+        "Error in last index"
+    );
+    assert!(
+        client.try_verify(&leaf, &branch, &leaf_index(1u64 << 32))
+            == Err(Ok(Error::MerkleTreeIndexOutOfRange.into())),
+        "Error index past the tree accepted"
+    );
+}
+
+#[test]
+fn reduce_matches_branch_root() {
+    let env = Env::default();
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let mut siblings = vec![&env];
+    for i in 0..32u8 {
+        siblings.push_back(BytesN::from_array(&env, &[i + 2; 32]));
+    }
+
+    for index in [0u64, 1, 6, 1 << 31, u64::from(u32::MAX)] {
+        assert!(
+            MerkleTree::reduce(env.clone(), leaf.clone(), siblings.clone(), index)
+                == MerkleTree::branch_root(
+                    env.clone(),
+                    leaf.clone(),
+                    siblings.clone(),
+                    leaf_index(index)
+                ),
+            "Error in index {}",
+            index
+        );
+    }
+
+    // A short path stops where the siblings do
+    let short = siblings.slice(0..3);
+    assert!(
+        MerkleTree::reduce(env.clone(), leaf.clone(), short.clone(), 5)
+            == MerkleTree::branch_root_at_depth(env.clone(), 3, leaf, short, leaf_index(5u64)),
+        "Error in short path"
+    );
+}
+
+#[test]
+fn verify_rejects_branch_of_wrong_length() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let proof = client.generate_proof(&0);
+    assert!(
+        client.verify(&leaf, &proof, &leaf_index(0)),
+        "Error in full proof"
+    );
+
+    let mut short = proof.clone();
+    short.pop_back_unchecked();
+    let mut long = proof.clone();
+    long.push_back(BytesN::from_array(&env, &[0; 32]));
+    for branch in [vec![&env], short, long] {
+        assert!(
+            client.try_verify(&leaf, &branch, &leaf_index(0))
+                == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+            "Error branch of {} siblings accepted",
+            branch.len()
+        );
+    }
+}
+
+#[test]
+#[should_panic]
+fn branch_root_at_depth_rejects_full_depth_branch() {
+    let env = Env::default();
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let branch = MerkleTree::zero_hashes(env.clone());
+    MerkleTree::branch_root_at_depth(env, 2, leaf, branch, leaf_index(0u64));
+}
+
+#[test]
+fn reset_starts_a_fresh_tree() {
+    let env = Env::default();
+    let genesis = BytesN::from_array(&env, &[9; 32]);
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            admin: Some(Address::random(&env)),
+            genesis: Some(genesis.clone()),
+            store_leaves: true,
+            mutable: true,
+            ..TreeConfig::default()
+        },
+    );
+    let fresh_root = client.get_root();
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    let old_root = client.get_root();
+
+    // Nobody signed for the admin
+    assert!(client.try_reset().is_err(), "Error in unauthorized reset");
+    assert!(client.get_count() == 2, "Error tree reset without admin");
+
+    env.mock_all_auths();
+    // Everything derived from the old tree
+    let owner = Address::random(&env);
+    let tag = BytesN::from_array(&env, &[5; 32]);
+    client.commit_root_reference(&owner, &tag);
+    client.update_leaf(&1, &BytesN::from_array(&env, &[1; 32]));
+    client.insert(&BytesN::from_array(&env, &[2; 32]));
+    client.freeze_root();
+    let proof = client.portable_proof(&1);
+    let nullifier = client.nullifier(&proof.leaf, &1);
+    client.verify_and_nullify(&proof.leaf, &proof.branch, &leaf_index(1), &nullifier);
+    for i in 0..3u8 {
+        client.mmr_append(&BytesN::from_array(&env, &[i; 32]));
+    }
+
+    client.reset();
+    assert!(client.get_count() == 1, "Error genesis not appended");
+    assert!(client.get_root() == fresh_root, "Error in reset root");
+    assert!(!client.is_known_root(&old_root), "Error old root kept");
+    assert!(!client.is_nullified(&nullifier), "Error nullifier kept");
+    assert!(client.hash_ops() == 0, "Error hash count kept");
+    assert!(
+        client.mmr_root() == Mmr::new(&env).root(&env),
+        "Error mmr kept"
+    );
+    env.as_contract(&client.address, || {
+        let storage = env.storage().persistent();
+        assert!(!storage.has(&VERSION), "Error version kept");
+        assert!(
+            !storage.has(&(VERSION_ROOT, 1u32)),
+            "Error version root kept"
+        );
+        assert!(!storage.has(&(ROOT_TAG, owner, tag)), "Error tag kept");
+        assert!(!storage.has(&ROOT_TAGS), "Error tag list kept");
+        assert!(!storage.has(&SPENT), "Error spent list kept");
+        for (height, index) in [(0u32, 0u64), (0, 1), (0, 2), (1, 0)] {
+            assert!(
+                !storage.has(&(MMR_NODE, height, index)),
+                "Error mmr node kept"
+            );
+        }
+        assert!(!env.storage().instance().has(&MMR), "Error mmr kept");
+    });
+
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(client.get_root() == old_root, "Error in insert after reset");
+
+    let client = setup_with_config(&env, TreeConfig::default());
+    assert!(
+        client.try_reset() == Err(Ok(Error::AdminNotSet.into())),
+        "Error reset without admin"
+    );
+}
+
+#[test]
+fn ttl_is_extended_on_access() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            ttl_extension: Some(10_000),
+            ..TreeConfig::default()
+        },
+    );
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    client.insert(&leaf);
+    client.insert(&BytesN::from_array(&env, &[2; 32]));
+    assert!(
+        client.ttl_remaining() == 10_000,
+        "Error in ttl after insert"
+    );
+
+    // Each read keeps the entries it touches alive past their first expiry
+    for _ in 0..3 {
+        env.ledger().with_mut(|li| li.sequence_number += 8_000);
+        assert!(client.get_leaf(&0) == Some(leaf.clone()), "Error in leaf");
+        assert!(client.hash_ops() == 1, "Error in hash count");
+        assert!(client.first_leaf_proof().is_some(), "Error in first path");
+        assert!(
+            client.is_known_root(&client.latest_checkpoint().1),
+            "Error in root history"
+        );
+    }
+    assert!(client.ttl_remaining() == 10_000, "Error in ttl after reads");
+
+    let client = setup_with_config(&env, TreeConfig::default());
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(client.ttl_remaining() == 0, "Error ttl without policy");
+}
+
+#[test]
+fn verify_continuity_needs_the_leaf_in_both_roots() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 2);
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    let old_root = client.get_root();
+    let old_branch = client.generate_proof(&1);
+
+    client.insert(&BytesN::from_array(&env, &[3; 32]));
+    let new_root = client.get_root();
+    let new_branch = client.generate_proof(&1);
+    assert!(old_branch != new_branch, "Error in unchanged proof");
+
+    assert!(
+        client.verify_continuity(
+            &leaf,
+            &old_branch,
+            &leaf_index(1),
+            &old_root,
+            &new_branch,
+            &new_root
+        ),
+        "Error in continuity"
+    );
+    assert!(
+        !client.verify_continuity(
+            &leaf,
+            &new_branch,
+            &leaf_index(1),
+            &old_root,
+            &new_branch,
+            &new_root
+        ),
+        "Error in wrong old proof"
+    );
+
+    // The new leaf is only in the new root
+    let late = BytesN::from_array(&env, &[3; 32]);
+    let late_branch = client.generate_proof(&2);
+    assert!(
+        !client.verify_continuity(
+            &late,
+            &late_branch,
+            &leaf_index(2),
+            &old_root,
+            &late_branch,
+            &new_root
+        ),
+        "Error leaf only in new root"
+    );
+}
+
+#[test]
+fn insert_batch_matches_single_inserts() {
+    let env = Env::default();
+    let mut nodes = vec![&env];
+    for i in 1..=5u8 {
+        nodes.push_back(BytesN::from_array(&env, &[i; 32]));
+    }
+
+    let client = setup_with_leaves(&env, 5);
+    let batched = setup_with_leaves(&env, 0);
+    assert!(
+        batched.insert_batch(&nodes.slice(0..2)) == (0, 2),
+        "Error in first batch"
+    );
+    assert!(
+        batched.insert_batch(&nodes.slice(2..5)) == (2, 3),
+        "Error in second batch"
+    );
+    assert_tree_invariants(&env, &batched);
+    assert!(
+        batched.insert_batch(&vec![&env]) == (5, 0),
+        "Error in empty batch"
+    );
+    assert!(
+        batched.get_tree() == client.get_tree(),
+        "Error in batched tree"
+    );
+    assert!(
+        batched.generate_proof(&4) == client.generate_proof(&4),
+        "Error in batched leaves"
+    );
+
+    let mut tree = MerkleTree::with_depth(&env, 2);
+    assert!(
+        tree.insert_batch(env.clone(), nodes.slice(0..4)) == (0, 4),
+        "Error in full batch"
+    );
+    assert!(tree.is_full(), "Error tree not full");
+}
+
+#[test]
+#[should_panic]
+fn insert_batch_fails_past_capacity() {
+    let env = Env::default();
+    let mut nodes = vec![&env];
+    for i in 1..=5u8 {
+        nodes.push_back(BytesN::from_array(&env, &[i; 32]));
+    }
+    let mut tree = MerkleTree::with_depth(&env, 2);
+    tree.insert_batch(env.clone(), nodes);
+}
+
+/// Hashes a pair by adding its bytes, plus one so empty subtrees differ.
+struct AddHasher;
+
+impl MerkleHasher for AddHasher {
+    fn hash_pair(&self, env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+        let (left, right) = (left.to_array(), right.to_array());
+        let node: [u8; 32] =
+            core::array::from_fn(|i| left[i].wrapping_add(right[i]).wrapping_add(1));
+        BytesN::from_array(env, &node)
+    }
+}
+
+#[test]
+fn custom_hasher_builds_the_tree() {
+    let env = Env::default();
+    let hasher = AddHasher;
+    let zeroes = hasher.zero_hashes(&env, 2);
+    assert!(
+        zeroes
+            == vec![
+                &env,
+                BytesN::from_array(&env, &[0; 32]),
+                BytesN::from_array(&env, &[1; 32])
+            ],
+        "Error in default zero hashes"
+    );
+
+    let mut tree = MerkleTree::with_depth(&env, 2);
+    tree.insert_with_hasher(env.clone(), &hasher, BytesN::from_array(&env, &[2; 32]));
+    tree.insert_with_hasher(env.clone(), &hasher, BytesN::from_array(&env, &[3; 32]));
+
+    // [2 + 3 + 1] with the empty pair [1], plus 1
+    let root = tree.root_with_hasher(env.clone(), &hasher);
+    assert!(
+        root == BytesN::from_array(&env, &[8; 32]),
+        "Error in custom root"
+    );
+    assert!(
+        root != tree.root(env.clone()),
+        "Error custom hasher not used"
+    );
+
+    let branch = vec![
+        &env,
+        BytesN::from_array(&env, &[2; 32]),
+        BytesN::from_array(&env, &[1; 32]),
+    ];
+    let leaf = BytesN::from_array(&env, &[3; 32]);
+    assert!(
+        MerkleTree::branch_root_with_hasher(
+            env.clone(),
+            &hasher,
+            2,
+            leaf,
+            branch,
+            leaf_index(1u64)
+        ) == root,
+        "Error in custom proof"
+    );
+}
+
+#[test]
+fn get_leaf_returns_stored_leaves() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+    for i in 0..3u32 {
+        assert!(
+            client.get_leaf(&i) == Some(BytesN::from_array(&env, &[i as u8 + 1; 32])),
+            "Error in leaf {}",
+            i
+        );
+    }
+    assert!(client.get_leaf(&3).is_none(), "Error leaf past the tree");
+
+    let client = setup_with_config(&env, TreeConfig::default());
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(
+        client.get_leaf(&0).is_none(),
+        "Error leaf without store_leaves"
+    );
+}
+
+#[test]
+fn verify_in_range_checks_the_index_window() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 4);
+    let leaf = BytesN::from_array(&env, &[3; 32]);
+    let branch = client.generate_proof(&2);
+
+    assert!(
+        client.verify_in_range(&leaf, &branch, &leaf_index(2), &2, &4),
+        "Error at window start"
+    );
+    assert!(
+        client.verify_in_range(&leaf, &branch, &leaf_index(2), &0, &3),
+        "Error at window end"
+    );
+    assert!(
+        !client.verify_in_range(&leaf, &branch, &leaf_index(2), &3, &4),
+        "Error below window"
+    );
+    assert!(
+        !client.verify_in_range(&leaf, &branch, &leaf_index(2), &0, &2),
+        "Error above window"
+    );
+    assert!(
+        !client.verify_in_range(&leaf, &branch, &leaf_index(2), &2, &2),
+        "Error in empty window"
+    );
+    assert!(
+        !client.verify_in_range(&leaf, &branch, &leaf_index(3), &0, &4),
+        "Error in wrong index"
+    );
+    assert!(
+        client.try_verify_in_range(&leaf, &branch, &leaf_index(4), &0, &8)
+            == Err(Ok(Error::MerkleTreeIndexOutOfRange.into())),
+        "Error past the last leaf"
+    );
+}
+
+#[test]
+fn namespaces_hold_independent_trees() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    let (first, second) = (symbol_short!("chain_a"), symbol_short!("chain_b"));
+
+    assert!(
+        client.get_namespace_tree(&first) == MerkleTree::new(&env),
+        "Error in unused namespace"
+    );
+    assert!(
+        client.get_namespace_root(&first) == client.get_root(),
+        "Error in unused namespace root"
+    );
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let other = BytesN::from_array(&env, &[2; 32]);
+    assert!(
+        client.insert_into(&first, &leaf) == 0,
+        "Error in first index"
+    );
+    assert!(
+        client.insert_into(&second, &other) == 0,
+        "Error in second index"
+    );
+    assert!(
+        client.insert_into(&second, &leaf) == 1,
+        "Error in second tree"
+    );
+    assert!(
+        client.get_namespace_tree(&first).count() == 1,
+        "Error in first count"
+    );
+    assert!(
+        client.get_namespace_tree(&second).count() == 2,
+        "Error in second count"
+    );
+
+    let proof = MerkleTree::zero_hashes(env.clone());
+    assert!(
+        client.verify_in_namespace(&first, &leaf, &proof, &leaf_index(0)),
+        "Error in first tree proof"
+    );
+    assert!(
+        !client.verify_in_namespace(&second, &leaf, &proof, &leaf_index(0)),
+        "Error proof of another tree"
+    );
+    let proof = zero_padded_proof(&env, vec![&env, other]);
+    assert!(
+        client.verify_in_namespace(&second, &leaf, &proof, &leaf_index(1)),
+        "Error in second tree proof"
+    );
+}
+
+#[test]
+fn verify_rejects_leaves_past_the_count() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 3);
+    let zero = BytesN::from_array(&env, &[0; 32]);
+
+    // Index 3 holds the empty leaf, with the same siblings as a real leaf there
+    let root = client.get_root();
+    let branch = client.generate_proof(&2);
+    let mut phantom = branch.clone();
+    phantom.set(0, BytesN::from_array(&env, &[3; 32]));
+    assert!(
+        MerkleTree::verify_against(
+            env.clone(),
+            zero.clone(),
+            phantom.clone(),
+            leaf_index(3u64),
+            root
+        ),
+        "Error in phantom proof"
+    );
+    assert!(
+        client.try_verify(&zero, &phantom, &leaf_index(3))
+            == Err(Ok(Error::MerkleTreeIndexOutOfRange.into())),
+        "Error phantom leaf accepted"
+    );
+
+    let leaf = BytesN::from_array(&env, &[3; 32]);
+    assert!(
+        client.verify(&leaf, &branch, &leaf_index(2)),
+        "Error in last leaf"
+    );
+}
+
+/// `keccak256(prefix || nodes...)`, as `HashAlgo::Keccak256Prefixed` hashes.
+fn prefixed_keccak(prefix: u8, nodes: &[[u8; 32]]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    let mut output = [0; 32];
+    hasher.update(&[prefix]);
+    for node in nodes {
+        hasher.update(node);
+    }
+    hasher.finalize(&mut output);
+    output
+}
+
+#[test]
+fn prefixed_tree_separates_leaves_from_nodes() {
+    let env = Env::default();
+    let algo = HashAlgo::Keccak256Prefixed;
+
+    let zeroes = algo.zero_hashes(&env, 32);
+    let mut zero = [0; 32];
+    for i in 0..32 {
+        assert!(
+            zeroes.get_unchecked(i).to_array() == zero,
+            "Error in zero hash {}",
+            i
+        );
+        zero = prefixed_keccak(0x01, &[zero, zero]);
+    }
+
+    let (a, b) = ([1; 32], [2; 32]);
+    let mut tree = MerkleTree::with_hash_algo(&env, 2, algo);
+    tree.insert(env.clone(), BytesN::from_array(&env, &a));
+    tree.insert(env.clone(), BytesN::from_array(&env, &b));
+
+    let (leaf_a, leaf_b) = (prefixed_keccak(0x00, &[a]), prefixed_keccak(0x00, &[b]));
+    let pair = prefixed_keccak(0x01, &[leaf_a, leaf_b]);
+    let empty = prefixed_keccak(0x01, &[[0; 32], [0; 32]]);
+    let root = prefixed_keccak(0x01, &[pair, empty]);
+    assert!(
+        tree.root(env.clone()).to_array() == root,
+        "Error in prefixed root"
+    );
+
+    let mut plain = MerkleTree::with_depth(&env, 2);
+    plain.insert(env.clone(), BytesN::from_array(&env, &a));
+    plain.insert(env.clone(), BytesN::from_array(&env, &b));
+    assert!(
+        plain.root(env.clone()) != tree.root(env.clone()),
+        "Error in plain root"
+    );
+
+    let branch = vec![
+        &env,
+        BytesN::from_array(&env, &leaf_b),
+        BytesN::from_array(&env, &empty),
+    ];
+    assert!(
+        MerkleTree::branch_root_with(
+            env.clone(),
+            algo,
+            2,
+            BytesN::from_array(&env, &a),
+            branch,
+            leaf_index(0u64)
+        ) == tree.root(env.clone()),
+        "Error in prefixed proof"
+    );
+
+    // The internal pair no longer passes as a leaf one level up
+    let branch = vec![&env, BytesN::from_array(&env, &empty)];
+    assert!(
+        MerkleTree::branch_root_with(
+            env.clone(),
+            algo,
+            1,
+            BytesN::from_array(&env, &pair),
+            branch,
+            leaf_index(0u64)
+        ) != tree.root(env.clone()),
+        "Error internal node accepted as leaf"
+    );
+}
+
+#[test]
+fn leaf_rule_lets_clients_rebuild_leaves() {
+    let env = Env::default();
+    let salt = BytesN::from_array(&env, &[7; 32]);
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            salt: Some(salt.clone()),
+            bind_index: true,
+            store_leaves: true,
+            ..TreeConfig::default()
+        },
+    );
+    let rule = client.leaf_rule();
+    assert!(
+        rule == LeafRule {
+            prefix: Bytes::new(&env),
+            salt: Some(salt),
+            bind_index: true,
+        },
+        "Error in leaf rule"
+    );
+
+    let value = [5; 32];
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    client.insert_salted(&BytesN::from_array(&env, &value));
+
+    // Replay the rule step by step, as a client would
+    let mut leaf = value;
+    if let Some(salt) = rule.salt {
+        leaf = MerkleTree::keccak256(vec![&env, salt.to_array(), leaf]);
+    }
+    if rule.bind_index {
+        let mut index = [0; 32];
+        index[31] = 1;
+        leaf = MerkleTree::keccak256(vec![&env, index, leaf]);
+    }
+    if !rule.prefix.is_empty() {
+        let mut bytes = rule.prefix.clone();
+        bytes.append(&Bytes::from_array(&env, &leaf));
+        leaf = MerkleTree::keccak256_bytes(&bytes);
+    }
+
+    let leaf = BytesN::from_array(&env, &leaf);
+    assert!(
+        client.get_leaf(&1) == Some(leaf.clone()),
+        "Error in rebuilt leaf"
+    );
+    assert!(
+        MerkleTree::branch_root(
+            env.clone(),
+            leaf,
+            client.generate_proof(&1),
+            leaf_index(1u64)
+        ) == client.get_root(),
+        "Error rebuilt leaf not in the tree"
+    );
+}
+
+#[test]
+fn keccak256_pair_matches_tree_hashing() {
+    let env = Env::default();
+    let left = BytesN::from_array(&env, &[1; 32]);
+    let right = BytesN::from_array(&env, &[2; 32]);
+
+    let mut bytes = [0; 64];
+    bytes[..32].copy_from_slice(&[1; 32]);
+    bytes[32..].copy_from_slice(&[2; 32]);
+    let mut expected = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(&bytes);
+    hasher.finalize(&mut expected);
+
+    let pair = MerkleTree::keccak256_pair(&env, &left, &right);
+    assert!(pair.to_array() == expected, "Error in pair hash");
+    assert!(
+        pair == MerkleTree::hash_pair(&env, HashAlgo::Keccak256, &left, &right),
+        "Error pair differs from the tree hash"
+    );
+    assert!(
+        pair != MerkleTree::keccak256_pair(&env, &right, &left),
+        "Error pair hash is not ordered"
+    );
+
+    let mut tree = MerkleTree::with_depth(&env, 1);
+    tree.insert(env.clone(), left);
+    tree.insert(env.clone(), right);
+    assert!(tree.root(env.clone()) == pair, "Error in tree root");
+}
+
+#[test]
+fn configured_depth_sets_capacity_and_proofs() {
+    let env = Env::default();
+    let config = TreeConfig {
+        depth: 3,
+        store_leaves: true,
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config.clone());
+    assert!(
+        client.try_initialize(&config) == Err(Ok(Error::AlreadyInitialized.into())),
+        "Error in second initialize"
+    );
+
+    let mut reference = MerkleTree::with_depth(&env, 3);
+    for i in 1..=8u8 {
+        let leaf = BytesN::from_array(&env, &[i; 32]);
+        client.insert(&leaf);
+        reference.insert(env.clone(), leaf);
+    }
+    assert!(client.get_tree() == reference, "Error in configured tree");
+    assert!(
+        client.get_root() == reference.root(env.clone()),
+        "Error in root"
+    );
+    assert!(client.is_full(), "Error tree not full");
+    assert!(
+        client.try_insert(&BytesN::from_array(&env, &[9; 32]))
+            == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error insert past capacity"
+    );
+
+    let proof = client.generate_proof(&5);
+    assert!(proof.len() == 3, "Error in proof length");
+    let leaf = BytesN::from_array(&env, &[6; 32]);
+    assert!(
+        client.verify(&leaf, &proof, &leaf_index(5)),
+        "Error in proof"
+    );
+    assert!(
+        client.verification_bundle().zero_hashes.len() == 3,
+        "Error in zero hashes"
+    );
+
+    for depth in [0, 33] {
+        let contract_id = env.register_contract(None, Contract);
+        let client = ContractClient::new(&env, &contract_id);
+        assert!(
+            client.try_initialize(&TreeConfig {
+                depth,
+                ..TreeConfig::default()
+            }) == Err(Ok(Error::InvalidDepth.into())),
+            "Error depth {} accepted",
+            depth
+        );
+    }
+}
+
+#[test]
+fn depth_is_capped_at_max_supported_depth() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    assert!(
+        client.max_supported_depth() == MAX_SUPPORTED_DEPTH,
+        "Error in cap"
+    );
+
+    assert!(
+        client.try_initialize(&TreeConfig {
+            depth: MAX_SUPPORTED_DEPTH + 1,
+            ..TreeConfig::default()
+        }) == Err(Ok(Error::InvalidDepth.into())),
+        "Error depth above the cap accepted"
+    );
+    client.initialize(&TreeConfig {
+        depth: MAX_SUPPORTED_DEPTH,
+        ..TreeConfig::default()
+    });
+    assert!(
+        client.get_tree().depth() == MAX_SUPPORTED_DEPTH,
+        "Error in depth at the cap"
+    );
+
+    let tree = MerkleTree::with_depth(&env, MAX_SUPPORTED_DEPTH);
+    assert!(
+        tree == MerkleTree::new(&env),
+        "Error in library depth at the cap"
+    );
+}
+
+#[test]
+fn try_insert_reports_full_tree() {
+    let env = Env::default();
+    let mut tree = MerkleTree::with_depth(&env, 1);
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+
+    assert!(
+        tree.try_insert(env.clone(), leaf.clone()) == Ok(0),
+        "Error in first insert"
+    );
+    assert!(
+        tree.try_insert(env.clone(), leaf.clone()) == Ok(1),
+        "Error in last insert"
+    );
+    let full = tree.clone();
+    assert!(
+        tree.try_insert(env.clone(), leaf) == Err(Error::MerkleTreeFull),
+        "Error insert into full tree"
+    );
+    assert!(tree == full, "Error full tree changed");
+}
+
+#[test]
+fn has_same_root_ignores_unread_frontier_entries() {
+    let env = Env::default();
+    let mut tree = MerkleTree::new(&env);
+    tree.insert(env.clone(), BytesN::from_array(&env, &[1; 32]));
+    tree.insert(env.clone(), BytesN::from_array(&env, &[2; 32]));
+
+    // With 2 leaves the root only reads level 1, leaf 0 was cleared below
+    let mut migrated = tree.clone();
+    migrated.branch.set(0, BytesN::from_array(&env, &[1; 32]));
+    assert!(migrated != tree, "Error in migrated frontier");
+    assert!(
+        tree.has_same_root(env.clone(), &migrated),
+        "Error in same root"
+    );
+
+    let mut other = tree.clone();
+    other.insert(env.clone(), BytesN::from_array(&env, &[3; 32]));
+    assert!(
+        !tree.has_same_root(env.clone(), &other),
+        "Error in other root"
+    );
+}
+
+#[test]
+fn tree_moves_from_instance_to_persistent_storage() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    // A tree written by the first version, in instance storage with a u32
+    // count and no depth or hash
+    let mut legacy = MerkleTree::new(&env);
+    legacy.insert(env.clone(), BytesN::from_array(&env, &[1; 32]));
+    let baseline = LegacyMerkleTree {
+        branch: legacy.branch(),
+        count: 1,
+    };
+    env.as_contract(&contract_id, || {
+        env.storage().instance().set(&TREE, &baseline);
+    });
+    assert!(client.get_tree() == legacy, "Error reading legacy tree");
+
+    client.insert(&BytesN::from_array(&env, &[2; 32]));
+    legacy.insert(env.clone(), BytesN::from_array(&env, &[2; 32]));
+    env.as_contract(&contract_id, || {
+        assert!(
+            !env.storage().instance().has(&TREE),
+            "Error legacy tree kept"
+        );
+        let stored: MerkleTree = env.storage().persistent().get(&TREE).unwrap();
+        assert!(stored == legacy, "Error in migrated tree");
+    });
+    assert!(client.get_tree() == legacy, "Error reading migrated tree");
+}
+
+#[test]
+fn root_from_leaves_rebuilds_past_roots() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            empty_leaf: Some(BytesN::from_array(&env, &[9; 32])),
+            ..TreeConfig::default()
+        },
+    );
+    let mut leaves = vec![&env];
+    let mut tree = MerkleTree::new(&env);
+    let mut past_root = client.get_root();
+    assert!(
+        MerkleTree::root_from_leaves(env.clone(), leaves.clone())
+            == MerkleTree::empty_root(env.clone()),
+        "Error in empty root"
+    );
+
+    for i in 1..=6u8 {
+        let leaf = BytesN::from_array(&env, &[i; 32]);
+        leaves.push_back(leaf.clone());
+        tree.insert(env.clone(), leaf.clone());
+        client.insert(&leaf);
+
+        assert!(
+            MerkleTree::root_from_leaves(env.clone(), leaves.clone()) == tree.root(env.clone()),
+            "Error in root after {} leaves",
+            i
+        );
+        assert!(
+            client.root_from_leaves(&leaves) == client.get_root(),
+            "Error in contract root after {} leaves",
+            i
+        );
+        if i == 2 {
+            past_root = client.get_root();
+        }
+    }
+    assert!(
+        client.root_from_leaves(&leaves.slice(0..2)) == past_root,
+        "Error in past root"
+    );
+}
+
+#[test]
+fn malformed_frontier_fails_with_invalid_vec_size() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    let namespace = symbol_short!("broken");
+
+    // One leaf but no frontier node for it
+    let malformed = MerkleTree {
+        branch: vec![&env],
+        count: 1,
+        depth: 32,
+        hash_algo: HashAlgo::Keccak256,
+    };
+    let mut tree = malformed.clone();
+    assert!(
+        tree.try_insert(env.clone(), BytesN::from_array(&env, &[1; 32]))
+            == Err(Error::MerkleTreeInvalidVecSize),
+        "Error insert into malformed tree"
+    );
+
+    env.as_contract(&contract_id, || {
+        env.storage()
+            .persistent()
+            .set(&(TREE, namespace.clone()), &malformed);
+    });
+    assert!(
+        client.try_get_namespace_root(&namespace)
+            == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+        "Error root of malformed tree"
+    );
+    assert!(
+        client.try_insert_into(&namespace, &BytesN::from_array(&env, &[1; 32]))
+            == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+        "Error contract insert into malformed tree"
+    );
+}
+
+#[test]
+fn get_branch_resumes_the_tree_off_chain() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 5);
+    let branch = client.get_branch();
+    assert!(branch == client.get_tree().branch(), "Error in branch");
+    assert!(branch.len() == 3, "Error in branch length");
+
+    // Level 2 holds the root of the first 4 leaves, level 0 the fifth leaf
+    let mut subtree = MerkleTree::with_depth(&env, 2);
+    for i in 1..=4u8 {
+        subtree.insert(env.clone(), BytesN::from_array(&env, &[i; 32]));
+    }
+    assert!(
+        branch.get_unchecked(2) == subtree.root(env.clone()),
+        "Error in level 2"
+    );
+    assert!(
+        branch.get_unchecked(0) == BytesN::from_array(&env, &[5; 32]),
+        "Error in level 0"
+    );
+
+    let mut mirror = MerkleTree {
+        branch,
+        count: client.get_count(),
+        depth: 32,
+        hash_algo: HashAlgo::Keccak256,
+    };
+    let leaf = BytesN::from_array(&env, &[6; 32]);
+    mirror.insert(env.clone(), leaf.clone());
+    client.insert(&leaf);
+    assert!(
+        mirror.root(env.clone()) == client.get_root(),
+        "Error in mirrored root"
+    );
+}
+
+#[test]
+fn contains_index_only_holds_filled_slots() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 2);
+    let tree = client.get_tree();
+    assert!(tree.contains_index(0), "Error in first slot");
+    assert!(tree.contains_index(1), "Error in last slot");
+    assert!(!tree.contains_index(2), "Error in free slot");
+    assert!(
+        !MerkleTree::new(&env).contains_index(0),
+        "Error in empty tree"
+    );
+
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let branch = client.generate_proof(&0);
+    assert!(
+        client.verify(&leaf, &branch, &leaf_index(0)),
+        "Error in filled slot"
+    );
+}
+
+#[test]
+fn get_depth_reports_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_with_config(&env, TreeConfig::default());
+    assert!(
+        client.get_depth() == TREE_DEPTH as u32,
+        "Error in default depth"
+    );
+    assert!(
+        MerkleTree::new(&env).capacity() == MAX_LEAVES,
+        "Error in default capacity"
+    );
+
+    let config = TreeConfig {
+        depth: 4,
+        store_leaves: true,
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config);
+    assert!(client.get_depth() == 4, "Error in configured depth");
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    let branch = client.generate_proof(&0);
+    assert!(branch.len() == client.get_depth(), "Error in proof length");
+}
+
+#[test]
+fn empty_root_follows_the_configured_depth() {
+    let env = Env::default();
+    let config = TreeConfig {
+        depth: 8,
+        empty_leaf: Some(BytesN::from_array(&env, &[7; 32])),
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config);
+    let empty_root = client.get_root();
+    assert!(client.is_empty_root(&empty_root), "Error in is_empty_root");
+    assert!(
+        !client.is_empty_root(&MerkleTree::empty_root(env.clone())),
+        "Error in default empty root"
+    );
+    assert!(client.prove_empty(), "Error in prove_empty");
+
+    client.insert(&BytesN::from_array(&env, &[1; 32]));
+    assert!(!client.prove_empty(), "Error after an insert");
+    assert!(
+        !client.is_empty_root(&client.get_root()),
+        "Error in populated root"
+    );
+}
+
+#[test]
+fn contains_all_applies_the_bounds_and_leaf_rule() {
+    let env = Env::default();
+    let client = setup_with_leaves(&env, 1);
+    let leaf = BytesN::from_array(&env, &[1; 32]);
+    let zero = BytesN::from_array(&env, &[0; 32]);
+
+    // The empty leaf at index 1 hashes to the root, but is past the count
+    let phantom = MerkleProof {
+        leaf: zero,
+        branch: zero_padded_proof(&env, vec![&env, leaf.clone()]),
+        index: LeafIndex(1),
+    };
+    assert!(
+        MerkleTree::branch_root(
+            env.clone(),
+            phantom.leaf.clone(),
+            phantom.branch.clone(),
+            leaf_index(1u64)
+        ) == client.get_root(),
+        "Error in phantom root"
+    );
+    assert!(
+        !client.contains_all(&vec![&env, phantom]),
+        "Error phantom leaf accepted"
+    );
+
+    let config = TreeConfig {
+        bind_index: true,
+        depth: 8,
+        store_leaves: true,
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config);
+    client.insert(&leaf);
+    let proof = MerkleProof {
+        leaf,
+        branch: client.generate_proof(&0),
+        index: LeafIndex(0),
+    };
+    assert!(
+        client.contains_all(&vec![&env, proof]),
+        "Error in bound leaf"
+    );
+}
+
+#[test]
+fn batch_cost_follows_the_configured_capacity() {
+    let env = Env::default();
+    let config = TreeConfig {
+        depth: 2,
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config);
+    assert!(client.batch_cost(&4) == 3, "Error in filling batch");
+    assert!(
+        client.try_batch_cost(&5) == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error in batch past capacity"
+    );
+    assert!(
+        client.try_batch_cost(&10) == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error in oversized batch"
+    );
+}
+
+#[test]
+fn root_with_ctx_only_hashes_the_path() {
+    let env = Env::default();
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+    MerkleTree::check_zero_hashes(&env, HashAlgo::Keccak256, &zeroes.get_unchecked(0), &zeroes);
+
+    let mut tree = MerkleTree::new(&env);
+    tree.insert(env.clone(), BytesN::from_array(&env, &[1; 32]));
+    let hashes = count_keccak_calls(|| {
+        tree.root_with_ctx(env.clone(), &zeroes.get_unchecked(0), zeroes.clone());
+    });
+    assert!(hashes == 32, "Error in root hashes");
+}
+
+#[test]
+fn verify_range_stays_within_the_filled_leaves() {
+    let env = Env::default();
+    let config = TreeConfig {
+        depth: 4,
+        store_leaves: true,
+        ..TreeConfig::default()
+    };
+    let client = setup_with_config(&env, config);
+    let (one, two) = (
+        BytesN::from_array(&env, &[1; 32]),
+        BytesN::from_array(&env, &[2; 32]),
+    );
+    client.insert(&one);
+    client.insert(&two);
+
+    // Leaves 0 and 1 fill the left pair, the zero hashes cover the rest
+    let zeroes = client.verification_bundle().zero_hashes;
+    let right = zeroes.slice(1..4);
+    assert!(
+        client.verify_range(
+            &vec![&env, one.clone(), two.clone()],
+            &0,
+            &vec![&env],
+            &right
+        ),
+        "Error in range at depth 4"
+    );
+
+    // The empty leaf at index 2 hashes to the same root, but isn't filled
+    let zero = BytesN::from_array(&env, &[0; 32]);
+    let pair = MerkleTree::keccak256_pair(&env, &one, &two);
+    let left = vec![&env, pair];
+    let mut right_path = zeroes.slice(2..4);
+    right_path.push_front(zero.clone());
+    assert!(
+        !client.verify_range(&vec![&env, zero], &2, &left, &right_path),
+        "Error range past the last leaf accepted"
+    );
+}
+
+#[test]
+#[should_panic]
+fn check_zero_hashes_rejects_a_tower_over_another_leaf() {
+    let env = Env::default();
+
+    // A consistent tower, but built over [7; 32] rather than the empty leaf
+    let zeroes = MerkleTree::zero_hashes_from(env.clone(), BytesN::from_array(&env, &[7; 32]));
+    MerkleTree::check_zero_hashes(
+        &env,
+        HashAlgo::Keccak256,
+        &BytesN::from_array(&env, &[0; 32]),
+        &zeroes,
+    );
+}
+
+#[test]
+fn leaves_until_stable_stops_at_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            depth: 2,
+            ..TreeConfig::default()
+        },
+    );
+
+    for i in 0..3u8 {
+        client.insert(&BytesN::from_array(&env, &[i + 1; 32]));
+    }
+    assert!(
+        client.leaves_until_stable(&0) == 1,
+        "Error before the last leaf"
+    );
+    client.insert(&BytesN::from_array(&env, &[4; 32]));
+    for index in 0..4u32 {
+        assert!(
+            client.leaves_until_stable(&index) == 0,
+            "Error in full tree"
+        );
+    }
+}
+
+#[test]
+fn namespaces_follow_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            depth: 4,
+            store_leaves: true,
+            ..TreeConfig::default()
+        },
+    );
+    let (first, second) = (symbol_short!("chain_a"), symbol_short!("chain_b"));
+
+    assert!(
+        client.get_namespace_tree(&first).depth() == 4,
+        "Error in unused namespace depth"
+    );
+    assert!(
+        client.get_namespace_root(&first) == client.get_root(),
+        "Error in unused namespace root"
+    );
+
+    // The main tree holds the same leaves, so it gives the expected proofs
+    for i in 0..3u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        client.insert_into(&first, &leaf);
+        client.insert(&leaf);
+    }
+    assert!(
+        client.get_namespace_root(&first) == client.get_root(),
+        "Error in namespace root at depth 4"
+    );
+
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    let proof = client.generate_proof(&1);
+    assert!(
+        client.verify_in_namespace(&first, &leaf, &proof, &leaf_index(1)),
+        "Error in namespace proof at depth 4"
+    );
+    assert!(
+        client.verify_in_either(&(second.clone(), first), &leaf, &proof, &leaf_index(1)),
+        "Error in either namespace at depth 4"
+    );
+    assert!(
+        !client.verify_in_namespace(&second, &leaf, &proof, &leaf_index(1)),
+        "Error in empty namespace"
+    );
+}
+
+#[test]
+fn insert_batch_saves_once_and_publishes_one_event() {
+    let env = Env::default();
+    let mut nodes = vec![&env];
+    for i in 1..=5u8 {
+        nodes.push_back(BytesN::from_array(&env, &[i; 32]));
+    }
+
+    let client = setup_with_leaves(&env, 5);
+    let batched = setup_with_leaves(&env, 0);
+    let previous_root = batched.get_root();
+    batched.insert_batch(&nodes);
+
+    let inserts = env
+        .events()
+        .all()
+        .iter()
+        .filter(|(_, topics, _)| *topics == (symbol_short!("insert"),).into_val(&env))
+        .count();
+    assert!(inserts == 0, "Error in per leaf events");
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert!(
+        topics == (symbol_short!("ins_batch"),).into_val(&env),
+        "Error in batch event topics"
+    );
+    let event = BatchInsertEvent::try_from_val(&env, &data).unwrap();
+    assert!(
+        event.start == 0 && event.leaves == nodes,
+        "Error in batch event leaves"
+    );
+    assert!(
+        event.previous_root == previous_root && event.root == batched.get_root(),
+        "Error in batch event roots"
+    );
+
+    // Everything derived from the leaves matches inserting them one by one
+    assert!(
+        batched.get_root() == client.get_root(),
+        "Error in batched root"
+    );
+    assert!(
+        batched.first_leaf_proof() == client.first_leaf_proof(),
+        "Error in batched first leaf proof"
+    );
+    assert!(
+        batched.hash_ops() == client.hash_ops(),
+        "Error in batched hash ops"
+    );
+    assert!(
+        batched.is_known_root(&client.get_root()),
+        "Error in batched root history"
+    );
+    assert_tree_invariants(&env, &batched);
+}
+
+#[test]
+fn every_verifier_applies_the_leaf_rule() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            store_leaves: true,
+            bind_index: true,
+            admin: Some(Address::random(&env)),
+            mutable: true,
+            ..TreeConfig::default()
+        },
+    );
+    let leaves = [1u8, 2, 3].map(|i| BytesN::from_array(&env, &[i; 32]));
+
+    client.insert(&leaves[0]);
+    assert!(client.verify_single(&leaves[0]), "Error in bound sole leaf");
+    client.insert(&leaves[1]);
+    client.insert(&leaves[2]);
+
+    let proof = client.generate_proof(&1);
+    assert!(
+        client
+            .verify_diagnostic(&leaves[1], &proof, &leaf_index(1))
+            .valid,
+        "Error in bound diagnostic"
+    );
+    assert!(
+        !client
+            .verify_diagnostic(&leaves[1], &proof, &leaf_index(2))
+            .valid,
+        "Error bound diagnostic replayed"
+    );
+
+    let owner = Address::random(&env);
+    let tag = BytesN::from_array(&env, &[5; 32]);
+    client.commit_root_reference(&owner, &tag);
+    assert!(
+        client.verify_against_tag(&owner, &tag, &leaves[1], &proof, &leaf_index(1)),
+        "Error in bound tag proof"
+    );
+
+    let before = client.get_root();
+    let new = BytesN::from_array(&env, &[9; 32]);
+    let version = client.update_leaf(&1, &new);
+    let updated = client.generate_proof(&1);
+    assert!(
+        client.verify_at_version(&new, &updated, &leaf_index(1), &version),
+        "Error in bound version proof"
+    );
+    assert!(
+        client.root_after_replace(&new, &leaves[1], &updated, &1) == Some(before),
+        "Error in bound replacement root"
+    );
+}
+
+#[test]
+fn step_root_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 0);
+
+    let mut frontier = vec![&env];
+    let mut count = 0;
+    for i in 0..16u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        let (next, next_count, root) = client.step_root(&frontier, &count, &leaf);
+        client.insert(&leaf);
+        assert!(
+            root == client.get_root(),
+            "Error in stepped root at depth 4"
+        );
+        frontier = next;
+        count = next_count;
+    }
+    let leaf = BytesN::from_array(&env, &[17; 32]);
+    assert!(
+        client.try_step_root(&frontier, &count, &leaf) == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error stepped past capacity"
+    );
+}
+
+#[test]
+fn verify_extension_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 3);
+    let old = client.get_tree();
+
+    let mut appended = vec![&env];
+    for i in 4..=5u8 {
+        let leaf = BytesN::from_array(&env, &[i; 32]);
+        client.insert(&leaf);
+        appended.push_back(leaf);
+    }
+    assert!(
+        client.verify_extension(&old.branch, &old.count, &appended, &client.get_root()),
+        "Error in extension at depth 4"
+    );
+
+    let mut overflow = vec![&env];
+    for i in 0..14u8 {
+        overflow.push_back(BytesN::from_array(&env, &[i; 32]));
+    }
+    assert!(
+        !client.verify_extension(&old.branch, &old.count, &overflow, &client.get_root()),
+        "Error extension past capacity"
+    );
+}
+
+#[test]
+fn verify_with_frontier_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 3);
+    let old = client.get_tree();
+    let old_proof = client.generate_proof(&1);
+    client.insert(&BytesN::from_array(&env, &[4; 32]));
+
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    assert!(
+        client.verify_with_frontier(&old.branch, &old.count, &leaf, &old_proof, &leaf_index(1)),
+        "Error in old proof at depth 4"
+    );
+
+    // The empty leaf at index 3 hashes to the old root, but wasn't filled yet
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+    let one = BytesN::from_array(&env, &[1; 32]);
+    let mut phantom = zeroes.slice(0..4);
+    phantom.set(0, BytesN::from_array(&env, &[3; 32]));
+    phantom.set(1, MerkleTree::keccak256_pair(&env, &one, &leaf));
+    assert!(
+        !client.verify_with_frontier(
+            &old.branch,
+            &old.count,
+            &zeroes.get_unchecked(0),
+            &phantom,
+            &leaf_index(3)
+        ),
+        "Error phantom leaf in old frontier"
+    );
+}
+
+#[test]
+fn audit_against_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 3);
+
+    let mut leaves = vec![&env];
+    for i in 1..=3u8 {
+        leaves.push_back(BytesN::from_array(&env, &[i; 32]));
+    }
+    let result = client.audit_against(&leaves);
+    assert!(
+        result.matches && result.computed_root == client.get_root(),
+        "Error in audit at depth 4"
+    );
+}
+
+#[test]
+fn portable_proof_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 5);
+
+    let proof = client.portable_proof(&4);
+    assert!(proof.branch.len() == 4, "Error in proof length");
+    assert!(proof.root == client.get_root(), "Error in portable root");
+    assert!(
+        client.verify(&proof.leaf, &proof.branch, &leaf_index(4)),
+        "Error in portable proof at depth 4"
+    );
+}
+
+#[test]
+fn first_leaf_proof_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 5);
+
+    let proof = client.first_leaf_proof().unwrap();
+    assert!(
+        proof == client.generate_proof(&0),
+        "Error in first leaf proof at depth 4"
+    );
+}
+
+#[test]
+fn final_root_inputs_follow_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 5);
+
+    let (left, right) = client.final_root_inputs();
+    assert!(
+        MerkleTree::keccak256_pair(&env, &left, &right) == client.get_root(),
+        "Error in top hash at depth 4"
+    );
+
+    for i in 6..=16u8 {
+        client.insert(&BytesN::from_array(&env, &[i; 32]));
+    }
+    assert!(
+        client.try_final_root_inputs() == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error in full tree"
+    );
+}
+
+#[test]
+fn empty_suffix_root_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 4);
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+
+    assert!(
+        client.empty_suffix_root() == zeroes.get_unchecked(2),
+        "Error in suffix root at depth 4"
+    );
+    for i in 5..=16u8 {
+        client.insert(&BytesN::from_array(&env, &[i; 32]));
+    }
+    assert!(
+        client.try_empty_suffix_root() == Err(Ok(Error::MerkleTreeFull.into())),
+        "Error in full tree"
+    );
+}
+
+#[test]
+fn verify_diagnostic_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 3);
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    let mut proof = client.generate_proof(&1);
+
+    let result = client.verify_diagnostic(&leaf, &proof, &leaf_index(1));
+    assert!(
+        result.valid && result.computed_root == result.expected_root,
+        "Error in diagnostic at depth 4"
+    );
+
+    proof.set(1, BytesN::from_array(&env, &[9; 32]));
+    let result = client.verify_diagnostic(&leaf, &proof, &leaf_index(1));
+    assert!(
+        !result.valid && result.divergence_level == Some(2),
+        "Error in divergence at depth 4"
+    );
+}
+
+#[test]
+fn verify_wide_and_top_down_follow_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 3);
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    let proof = client.generate_proof(&1);
+
+    let mut wide = vec![&env];
+    let mut top_down = vec![&env];
+    for sibling in proof.iter() {
+        let mut node = [7; 64];
+        node[..32].copy_from_slice(&sibling.to_array());
+        wide.push_back(BytesN::from_array(&env, &node));
+        top_down.push_front(sibling);
+    }
+    assert!(
+        client.verify_wide(&leaf, &wide, &leaf_index(1)),
+        "Error in wide proof at depth 4"
+    );
+    assert!(
+        client.verify_top_down(&leaf, &top_down, &leaf_index(1)),
+        "Error in top down proof at depth 4"
+    );
+    assert!(
+        client.try_verify_top_down(&leaf, &zero_padded_proof(&env, proof), &leaf_index(1))
+            == Err(Ok(Error::MerkleTreeInvalidVecSize.into())),
+        "Error in top down proof of depth 32"
+    );
+}
+
+#[test]
+fn verify_single_follows_the_configured_depth() {
+    let env = Env::default();
+    let client = setup_at_depth(&env, 4, 1);
+
+    assert!(
+        client.verify_single(&BytesN::from_array(&env, &[1; 32])),
+        "Error in sole leaf at depth 4"
+    );
+}
+
+#[test]
+fn contract_builds_trees_of_every_algorithm() {
+    let env = Env::default();
+    for algo in [HashAlgo::Sha256, HashAlgo::Keccak256Prefixed] {
+        let client = setup_with_config(
+            &env,
+            TreeConfig {
+                hash_algo: algo,
+                store_leaves: true,
+                depth: 4,
+                ..TreeConfig::default()
+            },
+        );
+        let mut tree = MerkleTree::with_hash_algo(&env, 4, algo);
+        assert!(
+            client.get_root() == tree.root(env.clone()),
+            "Error in empty root"
+        );
+
+        for i in 1..=5u8 {
+            let leaf = BytesN::from_array(&env, &[i; 32]);
+            client.insert(&leaf);
+            tree.insert(env.clone(), leaf);
+            assert_tree_invariants(&env, &client);
+        }
+        assert!(
+            client.get_root() == tree.root(env.clone()),
+            "Error in root of {:?}",
+            algo
+        );
+
+        for index in 0..5u32 {
+            let leaf = BytesN::from_array(&env, &[index as u8 + 1; 32]);
+            let proof = client.portable_proof(&index);
+            assert!(
+                proof.leaf == leaf
+                    && client.verify(&leaf, &proof.branch, &leaf_index(index as u64)),
+                "Error in proof of {:?}",
+                algo
+            );
+            assert!(
+                client
+                    .verify_diagnostic(&leaf, &proof.branch, &leaf_index(index as u64))
+                    .valid,
+                "Error in diagnostic of {:?}",
+                algo
+            );
+        }
+        assert!(
+            client.first_leaf_proof().unwrap() == client.generate_proof(&0),
+            "Error in first leaf proof of {:?}",
+            algo
+        );
+    }
+}
+
+#[test]
+fn verify_range_applies_the_leaf_rule() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            bind_index: true,
+            depth: 4,
+            ..TreeConfig::default()
+        },
+    );
+    let leaves = vec![
+        &env,
+        BytesN::from_array(&env, &[1; 32]),
+        BytesN::from_array(&env, &[2; 32]),
+    ];
+    for leaf in leaves.iter() {
+        client.insert(&leaf);
+    }
+
+    let right_path = MerkleTree::zero_hashes(env.clone()).slice(1..4);
+    assert!(
+        client.verify_range(&leaves, &0, &vec![&env], &right_path),
+        "Error in bound range"
+    );
+}
+
+#[test]
+#[should_panic]
+fn root_with_ctx_rejects_tampered_zeroes() {
+    let env = Env::default();
+    let tree = MerkleTree::new(&env);
+
+    let mut zeroes = MerkleTree::zero_hashes(env.clone());
+    zeroes.set(17, BytesN::from_array(&env, &[1; 32]));
+    tree.root_with_ctx(env.clone(), &zeroes.get_unchecked(0), zeroes);
+}
+
+#[test]
+#[should_panic]
+fn root_with_ctx_rejects_a_tower_over_another_leaf() {
+    let env = Env::default();
+    let tree = MerkleTree::new(&env);
+
+    // A consistent tower, but its base isn't the empty leaf
+    let zeroes = MerkleTree::zero_hashes_from(env.clone(), BytesN::from_array(&env, &[7; 32]));
+    tree.root_with_ctx(env.clone(), &BytesN::from_array(&env, &[0; 32]), zeroes);
+}
+
+#[test]
+fn insert_batch_reports_a_start_past_u32() {
+    let env = Env::default();
+    let mut tree = MerkleTree::new(&env);
+    tree.count = MAX_LEAVES;
+
+    assert!(
+        tree.insert_batch(env.clone(), vec![&env]) == (MAX_LEAVES, 0),
+        "Error in start of a full tree"
+    );
+}
+
+#[test]
+fn dual_root_follows_the_configured_tree() {
+    let env = Env::default();
+    for hash_algo in [HashAlgo::Keccak256Prefixed, HashAlgo::Sha256] {
+        let config = TreeConfig {
+            store_leaves: true,
+            hash_algo,
+            empty_leaf: Some(BytesN::from_array(&env, &[7; 32])),
+            depth: 6,
+            ..TreeConfig::default()
+        };
+        let client = setup_with_config(&env, config.clone());
+        let keccak = setup_with_config(
+            &env,
+            TreeConfig {
+                hash_algo: match hash_algo {
+                    HashAlgo::Sha256 => HashAlgo::Keccak256,
+                    _ => hash_algo,
+                },
+                ..config.clone()
+            },
+        );
+        let sha256 = setup_with_config(
+            &env,
+            TreeConfig {
+                hash_algo: HashAlgo::Sha256,
+                ..config
+            },
+        );
+        for i in 1..=5u8 {
+            let leaf = BytesN::from_array(&env, &[i; 32]);
+            client.insert(&leaf);
+            keccak.insert(&leaf);
+            sha256.insert(&leaf);
+        }
+
+        let (keccak_root, sha256_root) = client.dual_root();
+        assert!(keccak_root == keccak.get_root(), "Error in keccak root");
+        assert!(sha256_root == sha256.get_root(), "Error in sha256 root");
+    }
+}
+
+#[test]
+fn position_bound_tree_matches_independent_computation() {
+    let env = Env::default();
+    let keccak = |parts: &[[u8; 32]]| {
+        let mut hasher = Keccak::v256();
+        let mut output: [u8; 32] = [0; 32];
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize(&mut output);
+        output
+    };
+    let word = |value: u64| {
+        let mut word = [0u8; 32];
+        word[24..].copy_from_slice(&value.to_be_bytes());
+        word
+    };
+    let node = |level: u64, position: u64, left: [u8; 32], right: [u8; 32]| {
+        keccak(&[word(level), word(position), left, right])
+    };
+
+    // Five leaves of a depth 3 tree, the empty subtrees being plain zeros
+    let leaves: std::vec::Vec<[u8; 32]> = (1..=5u8).map(|i| [i; 32]).collect();
+    let z0 = [0u8; 32];
+    let z1 = keccak(&[z0, z0]);
+    let l1 = [
+        node(1, 0, leaves[0], leaves[1]),
+        node(1, 1, leaves[2], leaves[3]),
+        node(1, 2, leaves[4], z0),
+    ];
+    let l2 = [node(2, 0, l1[0], l1[1]), node(2, 1, l1[2], z1)];
+    let root = node(3, 0, l2[0], l2[1]);
+
+    let mut tree = MerkleTree::with_depth(&env, 3);
+    for leaf in leaves.iter() {
+        tree.insert_with_hasher(env.clone(), &PositionBound, BytesN::from_array(&env, leaf));
+    }
+    assert!(
+        tree.root_with_hasher(env.clone(), &PositionBound)
+            .to_array()
+            == root,
+        "Error in position-bound root"
+    );
+
+    let proofs = [(2u64, [leaves[3], l1[0], l2[1]]), (4, [z0, z1, l2[0]])];
+    for (index, siblings) in proofs {
+        let mut branch = vec![&env];
+        for sibling in siblings.iter() {
+            branch.push_back(BytesN::from_array(&env, sibling));
+        }
+        let computed = MerkleTree::position_bound_branch_root(
+            env.clone(),
+            3,
+            BytesN::from_array(&env, &leaves[index as usize]),
+            branch,
+            leaf_index(index),
+        );
+        assert!(computed.to_array() == root, "Error in position-bound proof");
+    }
+}
+
+#[test]
+fn verify_nested_follows_the_configured_hash() {
+    let env = Env::default();
+    let config = TreeConfig {
+        store_leaves: true,
+        hash_algo: HashAlgo::Keccak256Prefixed,
+        ..TreeConfig::default()
+    };
+
+    // The inner tree is a shallower one of another contract, same hash
+    let inner = setup_with_config(
+        &env,
+        TreeConfig {
+            depth: 3,
+            ..config.clone()
+        },
+    );
+    for i in 1..4u8 {
+        inner.insert(&BytesN::from_array(&env, &[i; 32]));
+    }
+    let inner_branch = inner.generate_proof(&1);
+
+    let client = setup_with_config(&env, config);
+    for i in 1..3u8 {
+        client.insert(&BytesN::from_array(&env, &[i; 32]));
+    }
+    client.insert(&inner.get_root());
+    let outer_branch = client.generate_proof(&2);
+
+    let leaf = BytesN::from_array(&env, &[2; 32]);
+    assert!(
+        client.verify_nested(
+            &leaf,
+            &inner_branch,
+            &leaf_index(1),
+            &outer_branch,
+            &leaf_index(2)
+        ),
+        "Error in nested proof"
+    );
+    assert!(
+        !client.verify_nested(
+            &leaf,
+            &inner_branch,
+            &leaf_index(0),
+            &outer_branch,
+            &leaf_index(2)
+        ),
+        "Error in wrong inner index"
+    );
+}
+
+#[test]
+fn kary_proof_rejects_an_index_past_capacity() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            fanout: 4,
+            ..TreeConfig::default()
+        },
+    );
+    let mut leaves = vec![&env];
+    for i in 0..5u8 {
+        let leaf = BytesN::from_array(&env, &[i + 1; 32]);
+        client.insert(&leaf);
+        leaves.push_back(leaf);
+    }
+
+    // 16 levels of 4 children reach 4^16 = MAX_LEAVES leaves, so that index
+    // would wrap around to leaf 0
+    let (_, proof) = quaternary_proof(&env, &leaves, 0);
+    let item = leaves.get_unchecked(0);
+    assert!(
+        client.verify_kary(&item, &proof, &leaf_index(0)),
+        "Error in 4-ary proof"
+    );
+    assert!(
+        client.try_verify_kary(&item, &proof, &leaf_index(MAX_LEAVES))
+            == Err(Ok(Error::InvalidRange.into())),
+        "Error index past capacity accepted"
+    );
+}
+
+#[test]
+fn hash_ops_count_leaf_hashes() {
+    let env = Env::default();
+    let client = setup_with_config(
+        &env,
+        TreeConfig {
+            hash_algo: HashAlgo::Keccak256Prefixed,
+            bind_index: true,
+            ..TreeConfig::default()
+        },
+    );
+
+    // Every leaf is bound to its index then prefix hashed before carrying
+    let expected: [u64; 5] = [2, 5, 7, 11, 13];
+    for (i, total) in expected.iter().enumerate() {
+        client.insert(&BytesN::from_array(&env, &[i as u8 + 1; 32]));
+        assert_tree_invariants(&env, &client);
+        assert!(client.hash_ops() == *total, "Error in hash ops");
+    }
+
+    let predicted = client.batch_cost(&4);
+    let before = client.hash_ops();
+    let mut preimages = vec![&env];
+    for i in 0..4u32 {
+        preimages.push_back(Bytes::from_array(&env, &i.to_be_bytes()));
+    }
+    client.insert_hashed(&preimages);
+    assert!(
+        predicted as u64 == client.hash_ops() - before,
+        "Error in batch cost"
+    );
+}
@@ -0,0 +1,182 @@
+//! Fixtures for the Nomad/Optics `Merkle.sol` library this crate is derived
+//! from. Leaf `i` is `keccak256(abi.encode(uint256(i)))`; the expected values
+//! come from a transliteration of the library's `Tree` (`insert`, `root`,
+//! `branchRoot`) with TREE_DEPTH 32, cross-checked against its well-known
+//! empty tree root.
+use crate::{Contract, ContractClient, LeafIndex, MerkleTree};
+use soroban_sdk::{vec, BytesN, Env, Vec};
+
+const EMPTY_ROOT: [u8; 32] = [
+    39, 174, 91, 160, 141, 114, 145, 201, 108, 140, 189, 220, 193, 72, 191, 72, 166, 214, 140, 121,
+    116, 185, 67, 86, 245, 55, 84, 239, 97, 113, 215, 87,
+];
+
+/// Root after inserting leaves `0..=i`.
+const ROOTS: [[u8; 32]; 10] = [
+    [
+        82, 6, 33, 167, 203, 45, 103, 115, 145, 50, 97, 205, 36, 163, 165, 105, 178, 80, 231, 81,
+        119, 83, 43, 191, 50, 234, 167, 93, 58, 231, 221, 187,
+    ],
+    [
+        228, 67, 4, 201, 20, 184, 241, 172, 15, 238, 232, 145, 17, 211, 253, 229, 78, 80, 191, 222,
+        19, 219, 87, 32, 182, 53, 125, 111, 249, 252, 8, 228,
+    ],
+    [
+        148, 132, 138, 208, 236, 25, 225, 56, 223, 68, 135, 201, 92, 146, 119, 43, 217, 140, 45,
+        71, 23, 106, 172, 154, 136, 46, 146, 29, 183, 146, 185, 9,
+    ],
+    [
+        3, 236, 126, 233, 200, 213, 209, 198, 103, 182, 5, 20, 85, 208, 55, 209, 166, 99, 165, 208,
+        118, 37, 130, 179, 5, 218, 243, 62, 144, 158, 69, 12,
+    ],
+    [
+        29, 249, 195, 45, 48, 37, 113, 238, 223, 19, 5, 126, 75, 112, 226, 130, 130, 29, 30, 36,
+        100, 190, 153, 224, 102, 178, 202, 226, 39, 6, 34, 244,
+    ],
+    [
+        199, 181, 184, 120, 47, 68, 157, 106, 138, 165, 246, 250, 145, 11, 131, 247, 61, 50, 97,
+        125, 167, 94, 8, 216, 245, 250, 35, 137, 55, 236, 126, 109,
+    ],
+    [
+        190, 231, 4, 62, 107, 222, 213, 240, 220, 99, 129, 166, 149, 176, 252, 157, 165, 173, 246,
+        32, 41, 251, 245, 175, 64, 65, 153, 214, 204, 253, 109, 102,
+    ],
+    [
+        184, 133, 38, 144, 210, 108, 76, 27, 1, 142, 55, 137, 31, 244, 123, 7, 161, 149, 33, 244,
+        246, 47, 173, 82, 13, 145, 136, 79, 105, 67, 64, 3,
+    ],
+    [
+        34, 60, 251, 54, 250, 237, 43, 30, 59, 9, 201, 138, 133, 116, 197, 112, 133, 202, 249, 214,
+        61, 248, 248, 164, 182, 74, 88, 125, 226, 161, 5, 225,
+    ],
+    [
+        106, 123, 124, 253, 87, 80, 120, 199, 11, 175, 217, 155, 89, 173, 208, 184, 136, 77, 145,
+        71, 153, 251, 42, 194, 210, 73, 118, 16, 150, 156, 245, 92,
+    ],
+];
+
+/// Lowest siblings of leaves 0, 6 and 9 once all 10 leaves are inserted, the
+/// siblings above are zero hashes.
+const PROOF_INDICES: [u64; 3] = [0, 6, 9];
+const PROOFS: [[[u8; 32]; 4]; 3] = [
+    [
+        [
+            177, 14, 45, 82, 118, 18, 7, 59, 38, 238, 205, 253, 113, 126, 106, 50, 12, 244, 75, 74,
+            250, 194, 176, 115, 45, 159, 203, 226, 183, 250, 12, 246,
+        ],
+        [
+            197, 253, 16, 106, 142, 82, 20, 131, 124, 98, 46, 95, 222, 241, 18, 177, 216, 58, 214,
+            222, 102, 190, 175, 181, 52, 81, 199, 120, 67, 201, 208, 78,
+        ],
+        [
+            187, 180, 69, 87, 74, 118, 127, 134, 162, 24, 228, 235, 121, 133, 72, 161, 110, 117,
+            67, 218, 108, 123, 16, 2, 22, 89, 15, 230, 112, 98, 105, 26,
+        ],
+        [
+            2, 223, 142, 114, 137, 76, 64, 70, 73, 162, 161, 239, 123, 233, 81, 16, 34, 118, 92,
+            133, 161, 142, 109, 96, 18, 47, 107, 86, 108, 196, 241, 83,
+        ],
+    ],
+    [
+        [
+            166, 108, 201, 40, 181, 237, 184, 42, 249, 189, 73, 146, 41, 84, 21, 90, 183, 176, 148,
+            38, 148, 190, 164, 206, 68, 102, 29, 154, 135, 54, 198, 136,
+        ],
+        [
+            61, 65, 79, 243, 249, 249, 144, 225, 187, 237, 5, 105, 124, 2, 1, 162, 75, 253, 91,
+            227, 120, 12, 69, 148, 118, 132, 59, 134, 89, 16, 252, 97,
+        ],
+        [
+            44, 36, 249, 47, 101, 205, 208, 253, 224, 38, 76, 31, 65, 250, 223, 23, 203, 53, 205,
+            255, 234, 202, 118, 158, 86, 115, 231, 43, 7, 43, 231, 7,
+        ],
+        [
+            2, 223, 142, 114, 137, 76, 64, 70, 73, 162, 161, 239, 123, 233, 81, 16, 34, 118, 92,
+            133, 161, 142, 109, 96, 18, 47, 107, 86, 108, 196, 241, 83,
+        ],
+    ],
+    [
+        [
+            243, 247, 169, 254, 54, 79, 170, 185, 59, 33, 109, 165, 10, 50, 20, 21, 79, 34, 160,
+            162, 180, 21, 178, 58, 132, 200, 22, 158, 139, 99, 110, 227,
+        ],
+        [
+            173, 50, 40, 182, 118, 247, 211, 205, 66, 132, 165, 68, 63, 23, 241, 150, 43, 54, 228,
+            145, 179, 10, 64, 178, 64, 88, 73, 229, 151, 186, 95, 181,
+        ],
+        [
+            180, 193, 25, 81, 149, 124, 111, 143, 100, 44, 74, 246, 28, 214, 178, 70, 64, 254, 198,
+            220, 127, 198, 7, 238, 130, 6, 169, 158, 146, 65, 13, 48,
+        ],
+        [
+            223, 63, 129, 230, 22, 87, 91, 219, 87, 121, 208, 96, 76, 115, 152, 224, 163, 154, 205,
+            147, 149, 156, 46, 176, 164, 138, 171, 238, 91, 236, 201, 41,
+        ],
+    ],
+];
+
+fn leaf(env: &Env, i: u64) -> BytesN<32> {
+    let mut word = [0; 32];
+    word[24..].copy_from_slice(&i.to_be_bytes());
+    BytesN::from_array(env, &MerkleTree::keccak256(vec![env, word]))
+}
+
+#[test]
+fn empty_root_matches_reference() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    assert!(
+        client.get_root().to_array() == EMPTY_ROOT,
+        "Error in empty root"
+    );
+}
+
+#[test]
+fn zero_hashes_match_reference() {
+    let env = Env::default();
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+
+    let mut expected = [0; 32];
+    for i in 0..zeroes.len() {
+        assert!(
+            zeroes.get_unchecked(i).to_array() == expected,
+            "Error in zero hash"
+        );
+        expected = MerkleTree::keccak256(vec![&env, expected, expected]);
+    }
+}
+
+#[test]
+fn roots_match_reference() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+
+    for (i, root) in ROOTS.iter().enumerate() {
+        client.insert(&leaf(&env, i as u64));
+        assert!(client.get_root().to_array() == *root, "Error in tree root");
+    }
+}
+
+#[test]
+fn proofs_match_reference() {
+    let env = Env::default();
+    let zeroes = MerkleTree::zero_hashes(env.clone());
+
+    for (index, siblings) in PROOF_INDICES.iter().zip(PROOFS.iter()) {
+        let mut proof: Vec<BytesN<32>> = zeroes.clone();
+        for (level, sibling) in siblings.iter().enumerate() {
+            proof.set(level as u32, BytesN::from_array(&env, sibling));
+        }
+
+        let root = MerkleTree::branch_root(
+            env.clone(),
+            leaf(&env, *index),
+            proof,
+            LeafIndex::new(*index, ROOTS.len() as u64).unwrap(),
+        );
+        assert!(root.to_array() == ROOTS[9], "Error in branch root");
+    }
+}